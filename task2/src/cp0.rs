@@ -0,0 +1,219 @@
+use crate::{DecodedInstruction, MipsSimulator};
+
+pub const EXCEPTION_VECTOR: u32 = 0x8000_0180;
+
+pub const BAD_VADDR: usize = 8;
+pub const COUNT: usize = 9;
+pub const COMPARE: usize = 11;
+pub const STATUS: usize = 12;
+pub const CAUSE: usize = 13;
+pub const EPC: usize = 14;
+
+// Status register bits.
+const STATUS_IE: u32 = 1 << 0;
+const STATUS_EXL: u32 = 1 << 1;
+const STATUS_ERL: u32 = 1 << 2;
+const STATUS_UM: u32 = 1 << 4;
+
+// Cause.IP7 is wired to the Count/Compare timer.
+const CAUSE_TIMER: u32 = 1 << 15;
+// Set when the faulting instruction was in a branch delay slot.
+const CAUSE_BD: u32 = 1 << 31;
+
+// User mode, interrupts enabled, every interrupt line unmasked.
+pub const STATUS_RESET: u32 = 0x0000_FF11;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Exception {
+    Interrupt = 0,
+    AddressErrorLoad = 4,
+    AddressErrorStore = 5,
+    Syscall = 8,
+    Breakpoint = 9,
+    ReservedInstruction = 10,
+    Overflow = 12,
+    Trap = 13,
+}
+
+impl Exception {
+    fn from_code(code: u32) -> Option<Exception> {
+        match code {
+            0 => Some(Exception::Interrupt),
+            4 => Some(Exception::AddressErrorLoad),
+            5 => Some(Exception::AddressErrorStore),
+            8 => Some(Exception::Syscall),
+            9 => Some(Exception::Breakpoint),
+            10 => Some(Exception::ReservedInstruction),
+            12 => Some(Exception::Overflow),
+            13 => Some(Exception::Trap),
+            _ => None,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Exception::Interrupt => "interrupt",
+            Exception::AddressErrorLoad => "address error on load or fetch",
+            Exception::AddressErrorStore => "address error on store",
+            Exception::Syscall => "syscall",
+            Exception::Breakpoint => "breakpoint",
+            Exception::ReservedInstruction => "reserved instruction",
+            Exception::Overflow => "arithmetic overflow",
+            Exception::Trap => "trap",
+        }
+    }
+}
+
+impl MipsSimulator {
+    pub fn get_cp0_register_index(&self, register: &str) -> usize {
+        match register
+            .strip_prefix('$')
+            .and_then(|n| n.parse::<usize>().ok())
+        {
+            Some(index) if index < 32 => index,
+            _ => {
+                self.report_error(&format!("Unknown CP0 register {}", register));
+                0
+            }
+        }
+    }
+
+    // Records the exception in CP0 and redirects to the exception vector. The
+    // faulting instruction must not have changed any other state by now.
+    pub fn raise_exception(&mut self, exception: Exception) {
+        // An exception in a delay slot restarts from the branch before it.
+        let in_delay_slot = self.delay_slot_target.take().is_some();
+        if self.cp0[STATUS] & STATUS_EXL == 0 {
+            if in_delay_slot {
+                self.cp0[EPC] = self.pc.wrapping_sub(4);
+                self.cp0[CAUSE] |= CAUSE_BD;
+            } else {
+                self.cp0[EPC] = self.pc;
+                self.cp0[CAUSE] &= !CAUSE_BD;
+            }
+        }
+        self.cp0[CAUSE] = (self.cp0[CAUSE] & !0x7C) | ((exception as u32) << 2);
+        self.cp0[STATUS] |= STATUS_EXL;
+        self.ll_bit = false;
+        self.pc = EXCEPTION_VECTOR;
+        self.exception_raised = true;
+    }
+
+    pub fn raise_address_error(&mut self, address: u32, store: bool) {
+        self.cp0[BAD_VADDR] = address;
+        if store {
+            self.raise_exception(Exception::AddressErrorStore);
+        } else {
+            self.raise_exception(Exception::AddressErrorLoad);
+        }
+    }
+
+    pub fn in_user_mode(&self) -> bool {
+        self.cp0[STATUS] & (STATUS_UM | STATUS_EXL | STATUS_ERL) == STATUS_UM
+    }
+
+    // Raises an address error for misaligned accesses, user-mode accesses to
+    // kernel space and (in MIPS64 mode) addresses outside the sign-extended
+    // 32-bit compatibility space. Returns the 32-bit address if the access may
+    // go ahead.
+    pub fn check_address(&mut self, address: u64, size: u32, store: bool) -> Option<u32> {
+        let compatible = address as i32 as u64 == address;
+        let address32 = address as u32;
+        let misaligned = !address32.is_multiple_of(size);
+        let privileged = address32 >= 0x8000_0000 && self.in_user_mode();
+        if !compatible || misaligned || privileged {
+            self.raise_address_error(address32, store);
+            return None;
+        }
+        Some(address32)
+    }
+
+    // Returns true when the instruction has already updated the program counter.
+    pub fn execute_cop0(&mut self, decoded: DecodedInstruction) -> bool {
+        match decoded.rs {
+            0 => self.registers[decoded.rt as usize] = self.cp0[decoded.rd as usize] as i32 as i64,
+            4 => self.execute_mtc0(decoded),
+            16 if decoded.funct == 24 => {
+                self.execute_eret();
+                return true;
+            }
+            _ => self.raise_exception(Exception::ReservedInstruction),
+        }
+        false
+    }
+
+    fn execute_mtc0(&mut self, decoded: DecodedInstruction) {
+        let value = self.registers[decoded.rt as usize] as u32;
+        let rd = decoded.rd as usize;
+        match rd {
+            // Writing Compare acknowledges the timer interrupt.
+            COMPARE => {
+                self.cp0[COMPARE] = value;
+                self.cp0[CAUSE] &= !CAUSE_TIMER;
+            }
+            // Only the software interrupt bits of Cause are writable.
+            CAUSE => self.cp0[CAUSE] = (self.cp0[CAUSE] & !0x300) | (value & 0x300),
+            _ => self.cp0[rd] = value,
+        }
+    }
+
+    fn execute_eret(&mut self) {
+        self.pc = self.cp0[EPC];
+        self.cp0[STATUS] &= !STATUS_EXL;
+        self.ll_bit = false;
+    }
+
+    // Advances Count once per instruction and raises the timer line when it
+    // reaches Compare.
+    pub fn tick_timer(&mut self) {
+        self.cp0[COUNT] = self.cp0[COUNT].wrapping_add(1);
+        if self.cp0[COUNT] == self.cp0[COMPARE] {
+            self.cp0[CAUSE] |= CAUSE_TIMER;
+        }
+    }
+
+    pub fn check_interrupts(&mut self) {
+        let status = self.cp0[STATUS];
+        let enabled = status & (STATUS_IE | STATUS_EXL | STATUS_ERL) == STATUS_IE;
+        if enabled && self.cp0[CAUSE] & status & 0xFF00 != 0 {
+            self.raise_exception(Exception::Interrupt);
+        }
+    }
+
+    pub fn report_unhandled_exception(&self) {
+        print!("{}", self.unhandled_exception_report());
+    }
+
+    // What the exception was, where it happened and, for address errors,
+    // the address at fault.
+    pub fn unhandled_exception_report(&self) -> String {
+        let code = (self.cp0[CAUSE] >> 2) & 0x1F;
+        let name = Exception::from_code(code).map_or("unknown exception", |e| e.name());
+        let epc = self.cp0[EPC];
+        let mut report = format!(
+            "Unhandled exception: {} (code {}) at PC 0x{:08X}\n",
+            name, code, epc
+        );
+        if let Some(&index) = self.instruction_index.get(&epc) {
+            report += &format!(
+                "Faulting instruction: {} [0x{:08X}]\n",
+                self.program[index].1, self.binary_program[index]
+            );
+        }
+        if code == Exception::AddressErrorLoad as u32 || code == Exception::AddressErrorStore as u32
+        {
+            report += &format!("Bad virtual address: 0x{:08X}\n", self.cp0[BAD_VADDR]);
+        }
+        report
+    }
+
+    pub fn print_cp0_registers(&self) {
+        println!("CP0 registers:");
+        println!("BadVAddr: 0x{:08X}", self.cp0[BAD_VADDR]);
+        println!("Count   : {}", self.cp0[COUNT]);
+        println!("Compare : {}", self.cp0[COMPARE]);
+        println!("Status  : 0x{:08X}", self.cp0[STATUS]);
+        println!("Cause   : 0x{:08X}", self.cp0[CAUSE]);
+        println!("EPC     : 0x{:08X}", self.cp0[EPC]);
+    }
+}
@@ -0,0 +1,192 @@
+use crate::expr::split_operands;
+use crate::MipsSimulator;
+
+// Splits `label: rest` into its parts. A colon inside a string or after an
+// operand does not start a label, so `.asciiz "a:b"` is left whole.
+pub fn split_label(line: &str) -> (Option<&str>, &str) {
+    if let Some((label, rest)) = line.split_once(':') {
+        let label = label.trim();
+        let is_identifier = !label.is_empty()
+            && label
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.');
+        if is_identifier {
+            return (Some(label), rest.trim());
+        }
+    }
+    (None, line)
+}
+
+// Reads every double-quoted string in the operands, decoding the usual
+// backslash escapes.
+fn parse_strings(arguments: &str) -> Vec<u8> {
+    let mut bytes = vec![];
+    let mut chars = arguments.chars();
+    while chars.any(|c| c == '"') {
+        loop {
+            match chars.next() {
+                None | Some('"') => break,
+                Some('\\') => match chars.next() {
+                    Some('n') => bytes.push(b'\n'),
+                    Some('t') => bytes.push(b'\t'),
+                    Some('r') => bytes.push(b'\r'),
+                    Some('0') => bytes.push(0),
+                    Some(c) => {
+                        let mut buffer = [0; 4];
+                        bytes.extend_from_slice(c.encode_utf8(&mut buffer).as_bytes());
+                    }
+                    None => break,
+                },
+                Some(c) => {
+                    let mut buffer = [0; 4];
+                    bytes.extend_from_slice(c.encode_utf8(&mut buffer).as_bytes());
+                }
+            }
+        }
+    }
+    bytes
+}
+
+impl MipsSimulator {
+    // Lays out one data directive at the end of the data segment. The labels
+    // that precede it are bound after any alignment padding, so they point at
+    // the first byte of the data itself.
+    pub fn assemble_data_directive(&mut self, line: &str, labels: &mut Vec<String>) {
+        let (directive, arguments) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+        let arguments = arguments.trim();
+        match directive {
+            ".byte" | ".half" | ".word" => {
+                let size = match directive {
+                    ".byte" => 1,
+                    ".half" => 2,
+                    _ => 4,
+                };
+                self.align_data(size);
+                self.bind_data_labels(labels);
+                for operand in split_operands(arguments) {
+                    self.write_data_value(self.next_free_address, size, &operand);
+                    self.advance_data(size);
+                }
+            }
+            ".float" => {
+                self.align_data(4);
+                self.bind_data_labels(labels);
+                for operand in split_operands(arguments) {
+                    let value = self.float_value(&operand) as f32;
+                    self.write_word(self.next_free_address, value.to_bits());
+                    self.advance_data(4);
+                }
+            }
+            ".double" => {
+                self.align_data(8);
+                self.bind_data_labels(labels);
+                for operand in split_operands(arguments) {
+                    let value = self.float_value(&operand);
+                    self.write_doubleword(self.next_free_address, value.to_bits());
+                    self.advance_data(8);
+                }
+            }
+            ".ascii" | ".asciiz" => {
+                self.bind_data_labels(labels);
+                let mut bytes = parse_strings(arguments);
+                if directive == ".asciiz" {
+                    bytes.push(0);
+                }
+                for byte in bytes {
+                    self.write_byte(self.next_free_address, byte);
+                    self.advance_data(1);
+                }
+            }
+            // Memory reads back as zero until written, so skipping is enough.
+            ".space" => {
+                self.bind_data_labels(labels);
+                let size = self.immediate_operand(arguments).max(0);
+                match u32::try_from(size) {
+                    Ok(size) => self.advance_data(size),
+                    Err(_) => self.report_error("Data segment overflow"),
+                }
+            }
+            ".align" => {
+                let power = self.immediate_operand(arguments).clamp(0, 31);
+                self.align_data(1 << power);
+            }
+            // `.eqv NAME value` and `.set NAME, value` define constants.
+            // Assembler options such as `.set noreorder` are accepted and
+            // ignored.
+            ".eqv" | ".set" => {
+                let (name, value) = arguments
+                    .split_once(|c: char| c == ',' || c.is_whitespace())
+                    .unwrap_or((arguments, ""));
+                if !value.trim().is_empty() {
+                    let value = self.immediate_operand(value.trim_start_matches([',', ' ']));
+                    self.constants.insert(name.to_string(), value);
+                }
+            }
+            _ => self.report_error(&format!("Unknown directive {}", directive)),
+        }
+    }
+
+    // Labels that were waiting for data get the current data address.
+    pub fn bind_data_labels(&mut self, labels: &mut Vec<String>) {
+        for label in labels.drain(..) {
+            self.labels.insert(label, self.next_free_address);
+        }
+    }
+
+    pub fn align_data(&mut self, alignment: u32) {
+        match self.next_free_address.checked_next_multiple_of(alignment) {
+            Some(address) => self.next_free_address = address,
+            None => self.report_error("Data segment overflow"),
+        }
+    }
+
+    // Running off the end of memory is reported and leaves the address as it
+    // was.
+    fn advance_data(&mut self, size: u32) {
+        match self.next_free_address.checked_add(size) {
+            Some(address) => self.next_free_address = address,
+            None => self.report_error("Data segment overflow"),
+        }
+    }
+
+    // Values that refer to labels defined further down are written once
+    // loading has finished.
+    fn write_data_value(&mut self, address: u32, size: u32, expression: &str) {
+        match self.try_evaluate(expression) {
+            Ok(value) => {
+                self.write_sized(address, size, value);
+                self.record_data_relocation(address, size, expression);
+            }
+            Err(_) => self.data_fixups.push((
+                address,
+                size,
+                expression.to_string(),
+                self.source_location.clone(),
+            )),
+        }
+    }
+
+    pub fn resolve_data_fixups(&mut self) {
+        for (address, size, expression, location) in std::mem::take(&mut self.data_fixups) {
+            self.source_location = location;
+            let value = self.immediate_operand(&expression);
+            self.write_sized(address, size, value);
+            self.record_data_relocation(address, size, &expression);
+        }
+    }
+
+    fn write_sized(&mut self, address: u32, size: u32, value: i64) {
+        match size {
+            1 => self.write_byte(address, value as u8),
+            2 => self.write_half(address, value as u16),
+            _ => self.write_word(address, value as u32),
+        }
+    }
+
+    // A floating-point literal, or an integer expression such as a constant.
+    fn float_value(&self, operand: &str) -> f64 {
+        operand
+            .parse::<f64>()
+            .unwrap_or_else(|_| self.immediate_operand(operand) as f64)
+    }
+}
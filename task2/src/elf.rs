@@ -0,0 +1,765 @@
+use std::collections::{BTreeMap, HashSet};
+use std::ops::Range;
+
+use crate::cp0::EXCEPTION_VECTOR;
+use crate::expr::{evaluate, referenced_symbols, split_memory_operand};
+use crate::fpu::COP1;
+use crate::link::ObjectUnit;
+use crate::{MipsSimulator, DATA_BASE};
+
+const ET_REL: u16 = 1;
+const ET_EXEC: u16 = 2;
+const EM_MIPS: u16 = 8;
+const EHDR_SIZE: usize = 52;
+const PHDR_SIZE: usize = 32;
+const SHDR_SIZE: usize = 40;
+const SYMBOL_SIZE: u32 = 16;
+const REL_SIZE: u32 = 8;
+
+// MIPS32 release 2 code using the o32 ABI, or MIPS64 release 2.
+const EF_MIPS_ARCH_32R2: u32 = 0x7000_0000;
+const EF_MIPS_ARCH_64R2: u32 = 0x8000_0000;
+const EF_MIPS_ABI_O32: u32 = 0x0000_1000;
+
+const SHT_PROGBITS: u32 = 1;
+const SHT_SYMTAB: u32 = 2;
+const SHT_STRTAB: u32 = 3;
+const SHT_NOBITS: u32 = 8;
+const SHT_REL: u32 = 9;
+const SHF_WRITE: u32 = 1;
+const SHF_ALLOC: u32 = 2;
+const SHF_EXECINSTR: u32 = 4;
+const SHN_UNDEF: u16 = 0;
+const SHN_ABS: u16 = 0xFFF1;
+
+const PT_LOAD: u32 = 1;
+const PF_X: u32 = 1;
+const PF_W: u32 = 2;
+const PF_R: u32 = 4;
+
+const STB_LOCAL: u8 = 0;
+const STB_GLOBAL: u8 = 1;
+const STT_NOTYPE: u8 = 0;
+const STT_SECTION: u8 = 3;
+
+const R_MIPS_16: u32 = 1;
+const R_MIPS_32: u32 = 2;
+const R_MIPS_26: u32 = 4;
+const R_MIPS_HI16: u32 = 5;
+const R_MIPS_LO16: u32 = 6;
+const R_MIPS_PC16: u32 = 10;
+
+// A field whose value depends on where a label ends up. `field` is what the
+// field holds in an object file: the target's offset from the symbol,
+// encoded the way the instruction encodes it, as the REL format expects.
+pub struct Relocation {
+    pub address: u32,
+    pub kind: u32,
+    pub symbol: String,
+    pub field: u32,
+}
+
+fn field_mask(kind: u32) -> u32 {
+    match kind {
+        R_MIPS_26 => 0x03FF_FFFF,
+        R_MIPS_32 => 0xFFFF_FFFF,
+        _ => 0xFFFF,
+    }
+}
+
+struct Section {
+    name: String,
+    kind: u32,
+    flags: u32,
+    address: u32,
+    contents: Vec<u8>,
+    // The size in memory, which is larger than the contents only for .bss.
+    size: u32,
+    link: u32,
+    info: u32,
+    align: u32,
+    entry_size: u32,
+}
+
+impl Section {
+    fn new(name: &str, kind: u32, flags: u32, address: u32, contents: Vec<u8>) -> Section {
+        Section {
+            name: name.to_string(),
+            kind,
+            flags,
+            address,
+            size: contents.len() as u32,
+            contents,
+            link: 0,
+            info: 0,
+            align: 4,
+            entry_size: 0,
+        }
+    }
+
+    fn contains(&self, address: u32) -> bool {
+        (self.address..self.address + self.size).contains(&address)
+    }
+}
+
+// The section a label belongs to. A label just past the end of a section,
+// with nothing following it, still belongs to that section.
+fn section_of(sections: &[Section], address: u32) -> Option<usize> {
+    sections
+        .iter()
+        .position(|section| section.contains(address))
+        .or_else(|| {
+            sections.iter().position(|section| {
+                section.flags & SHF_ALLOC != 0 && section.address + section.size == address
+            })
+        })
+}
+
+struct Symbol {
+    name: String,
+    value: u32,
+    section: u16,
+    binding: u8,
+    kind: u8,
+}
+
+// Appends a NUL-terminated string and returns its offset.
+fn add_string(table: &mut Vec<u8>, name: &str) -> u32 {
+    let offset = table.len() as u32;
+    table.extend_from_slice(name.as_bytes());
+    table.push(0);
+    offset
+}
+
+struct Encoder {
+    big_endian: bool,
+    bytes: Vec<u8>,
+}
+
+impl Encoder {
+    fn new(big_endian: bool) -> Encoder {
+        Encoder {
+            big_endian,
+            bytes: vec![],
+        }
+    }
+
+    fn half(&mut self, value: u16) {
+        let bytes = if self.big_endian {
+            value.to_be_bytes()
+        } else {
+            value.to_le_bytes()
+        };
+        self.bytes.extend_from_slice(&bytes);
+    }
+
+    fn word(&mut self, value: u32) {
+        let bytes = if self.big_endian {
+            value.to_be_bytes()
+        } else {
+            value.to_le_bytes()
+        };
+        self.bytes.extend_from_slice(&bytes);
+    }
+
+    fn pad_to(&mut self, offset: usize) {
+        self.bytes.resize(offset, 0);
+    }
+}
+
+struct Reader<'a> {
+    big_endian: bool,
+    bytes: &'a [u8],
+}
+
+impl Reader<'_> {
+    fn half(&self, offset: usize) -> Result<u16, String> {
+        let bytes: [u8; 2] = self
+            .bytes
+            .get(offset..offset + 2)
+            .and_then(|bytes| bytes.try_into().ok())
+            .ok_or("file is truncated")?;
+        Ok(if self.big_endian {
+            u16::from_be_bytes(bytes)
+        } else {
+            u16::from_le_bytes(bytes)
+        })
+    }
+
+    fn word(&self, offset: usize) -> Result<u32, String> {
+        let bytes: [u8; 4] = self
+            .bytes
+            .get(offset..offset + 4)
+            .and_then(|bytes| bytes.try_into().ok())
+            .ok_or("file is truncated")?;
+        Ok(if self.big_endian {
+            u32::from_be_bytes(bytes)
+        } else {
+            u32::from_le_bytes(bytes)
+        })
+    }
+
+    fn string(&self, offset: usize) -> String {
+        let bytes = self.bytes.get(offset..).unwrap_or_default();
+        let end = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+        String::from_utf8_lossy(&bytes[..end]).into_owned()
+    }
+}
+
+pub fn is_elf_file(filename: &str) -> bool {
+    let mut magic = [0; 4];
+    std::fs::File::open(filename)
+        .and_then(|mut file| std::io::Read::read_exact(&mut file, &mut magic))
+        .is_ok()
+        && magic == *b"\x7fELF"
+}
+
+impl MipsSimulator {
+    // Notes the field of an assembled instruction that depends on where a
+    // label ends up. The kind follows from the encoding: jumps take 26 bits,
+    // branches a PC-relative offset, and everything else a 16-bit immediate.
+    pub fn record_relocation(&mut self, address: u32, instruction: u32, parts: &[String]) {
+        if parts.len() < 2 {
+            return;
+        }
+        let operand = &parts[parts.len() - 1];
+        let (opcode, rs, rt) = (
+            instruction >> 26,
+            (instruction >> 21) & 0x1F,
+            (instruction >> 16) & 0x1F,
+        );
+        let kind = match opcode {
+            2 | 3 => R_MIPS_26,
+            4..=7 => R_MIPS_PC16,
+            1 if matches!(rt, 0 | 1 | 16 | 17) => R_MIPS_PC16,
+            COP1 if rs == 8 => R_MIPS_PC16,
+            0 | 16 | COP1 | 28 | 31 => return,
+            _ if operand.contains("%hi") => R_MIPS_HI16,
+            _ if operand.contains("%lo") => R_MIPS_LO16,
+            _ => R_MIPS_16,
+        };
+        let expression = split_memory_operand(operand).0;
+        let Some((symbol, offset)) = self.relocatable_value(expression) else {
+            return;
+        };
+        let field = match kind {
+            R_MIPS_26 => (offset >> 2) as u32,
+            R_MIPS_PC16 => ((offset - 4) >> 2) as u32,
+            _ => offset as u32,
+        } & field_mask(kind);
+        self.relocations.push(Relocation {
+            address,
+            kind,
+            symbol,
+            field,
+        });
+    }
+
+    // Words of data that hold a label's address need relocating too.
+    pub fn record_data_relocation(&mut self, address: u32, size: u32, expression: &str) {
+        if size != 4 {
+            return;
+        }
+        if let Some((symbol, offset)) = self.relocatable_value(expression) {
+            self.relocations.push(Relocation {
+                address,
+                kind: R_MIPS_32,
+                symbol,
+                field: offset as u32,
+            });
+        }
+    }
+
+    // An expression can be relocated when it refers to exactly one label;
+    // the difference of two labels does not move. Returns the label and the
+    // expression's value with that label taken to be zero.
+    fn relocatable_value(&self, expression: &str) -> Option<(String, i64)> {
+        let mut labels = referenced_symbols(expression)
+            .into_iter()
+            .filter(|name| !self.constants.contains_key(name) && self.labels.contains_key(name));
+        let symbol = labels.next()?;
+        if labels.next().is_some() {
+            return None;
+        }
+        let lookup = |name: &str| {
+            if name == symbol {
+                Some(0)
+            } else {
+                self.symbol_value(name)
+            }
+        };
+        let offset = evaluate(expression, &lookup).ok()?;
+        Some((symbol, offset))
+    }
+
+    // One section for user text, plus one for kernel text if there is any.
+    fn text_sections(&self, indices: Range<usize>) -> Vec<Section> {
+        let mut sections = vec![];
+        for (name, kernel) in [(".text", false), (".ktext", true)] {
+            let words: BTreeMap<u32, u32> = indices
+                .clone()
+                .filter(|&i| (self.program[i].0 >= EXCEPTION_VECTOR) == kernel)
+                .filter_map(|i| Some((self.program[i].0, *self.binary_program.get(i)?)))
+                .collect();
+            let base = match words.keys().next() {
+                Some(&base) => base,
+                None if kernel => continue,
+                None => crate::TEXT_BASE,
+            };
+            let mut contents = Encoder::new(self.big_endian);
+            for (&address, &word) in &words {
+                contents.pad_to((address - base) as usize);
+                contents.word(word);
+            }
+            sections.push(Section::new(
+                name,
+                SHT_PROGBITS,
+                SHF_ALLOC | SHF_EXECINSTR,
+                base,
+                contents.bytes,
+            ));
+        }
+        sections
+    }
+
+    // Everything up to the last byte written is .data; the reserved space
+    // after it is .bss, which takes no room in the file.
+    fn data_sections(&self, range: Range<u32>) -> Vec<Section> {
+        let initialised_end = self
+            .memory
+            .keys()
+            .copied()
+            .filter(|address| range.contains(address))
+            .max()
+            .map_or(range.start, |address| address + 1);
+        let contents = (range.start..initialised_end)
+            .map(|address| self.read_byte(address))
+            .collect();
+        let mut data = Section::new(
+            ".data",
+            SHT_PROGBITS,
+            SHF_ALLOC | SHF_WRITE,
+            range.start,
+            contents,
+        );
+        data.align = 8;
+        let mut bss = Section::new(
+            ".bss",
+            SHT_NOBITS,
+            SHF_ALLOC | SHF_WRITE,
+            initialised_end,
+            vec![],
+        );
+        bss.size = range.end.max(initialised_end) - initialised_end;
+        bss.align = 1;
+        vec![data, bss]
+    }
+
+    // Writes the linked program as an executable that starts at the current
+    // program counter.
+    pub fn write_elf_executable(&self, filename: &str) -> std::io::Result<()> {
+        let mut sections = self.text_sections(0..self.program.len());
+        sections.extend(self.data_sections(DATA_BASE..self.next_free_address));
+
+        let mut labels: Vec<(&String, u32, bool)> = vec![];
+        if self.units.is_empty() {
+            labels.extend(
+                self.labels
+                    .iter()
+                    .map(|(name, &address)| (name, address, false)),
+            );
+        }
+        for unit in &self.units {
+            labels.extend(
+                unit.labels
+                    .iter()
+                    .map(|(name, &address)| (name, address, unit.globals.contains_key(name))),
+            );
+        }
+        let symbols = labels
+            .into_iter()
+            .map(|(name, address, global)| Symbol {
+                name: name.clone(),
+                value: address,
+                section: section_of(&sections, address).map_or(SHN_ABS, |index| index as u16 + 1),
+                binding: if global { STB_GLOBAL } else { STB_LOCAL },
+                kind: STT_NOTYPE,
+            })
+            .collect();
+        self.write_elf_file(filename, ET_EXEC, self.pc, sections, symbols, vec![])
+    }
+
+    // Writes one unit as a relocatable object. Its sections start at zero,
+    // and every field that refers to a label holds only the offset from that
+    // label, with a relocation saying which label to add.
+    pub fn write_elf_object(&self, unit: &ObjectUnit, filename: &str) -> std::io::Result<()> {
+        let mut sections = self.text_sections(unit.program.clone());
+        sections.extend(self.data_sections(unit.data.clone()));
+
+        let mut relocations = vec![];
+        for relocation in &self.relocations {
+            let Some(index) = sections
+                .iter()
+                .position(|section| section.contains(relocation.address))
+            else {
+                continue;
+            };
+            // A branch within its own section does not move relative to its
+            // target.
+            let target = unit.labels.get(&relocation.symbol);
+            if relocation.kind == R_MIPS_PC16
+                && target.is_some_and(|&address| section_of(&sections, address) == Some(index))
+            {
+                continue;
+            }
+            let section = &mut sections[index];
+            let offset = (relocation.address - section.address) as usize;
+            let word = Reader {
+                big_endian: self.big_endian,
+                bytes: &section.contents,
+            }
+            .word(offset)
+            .unwrap_or(0);
+            let mask = field_mask(relocation.kind);
+            let mut patched = Encoder::new(self.big_endian);
+            patched.word((word & !mask) | (relocation.field & mask));
+            section.contents[offset..offset + 4].copy_from_slice(&patched.bytes);
+            relocations.push((
+                index,
+                offset as u32,
+                relocation.symbol.clone(),
+                relocation.kind,
+            ));
+        }
+
+        let mut symbols: Vec<Symbol> = unit
+            .labels
+            .iter()
+            .map(|(name, &address)| {
+                let section = section_of(&sections, address);
+                Symbol {
+                    name: name.clone(),
+                    value: section.map_or(address, |index| address - sections[index].address),
+                    section: section.map_or(SHN_ABS, |index| index as u16 + 1),
+                    binding: if unit.globals.contains_key(name) {
+                        STB_GLOBAL
+                    } else {
+                        STB_LOCAL
+                    },
+                    kind: STT_NOTYPE,
+                }
+            })
+            .collect();
+        let mut undefined = HashSet::new();
+        for (_, _, symbol, _) in &relocations {
+            if !unit.labels.contains_key(symbol) && undefined.insert(symbol.clone()) {
+                symbols.push(Symbol {
+                    name: symbol.clone(),
+                    value: 0,
+                    section: SHN_UNDEF,
+                    binding: STB_GLOBAL,
+                    kind: STT_NOTYPE,
+                });
+            }
+        }
+        for section in &mut sections {
+            section.address = 0;
+        }
+        self.write_elf_file(filename, ET_REL, 0, sections, symbols, relocations)
+    }
+
+    // Lays out the header, program headers for executables, the given
+    // sections, their relocations, the symbol table and the section headers.
+    fn write_elf_file(
+        &self,
+        filename: &str,
+        file_type: u16,
+        entry: u32,
+        mut sections: Vec<Section>,
+        mut symbols: Vec<Symbol>,
+        relocations: Vec<(usize, u32, String, u32)>,
+    ) -> std::io::Result<()> {
+        let content_sections = sections.len();
+        let rel_targets: Vec<usize> = (0..content_sections)
+            .filter(|&index| relocations.iter().any(|(target, ..)| *target == index))
+            .collect();
+        let symtab_index = (content_sections + rel_targets.len() + 1) as u32;
+
+        // Locals must come before globals. Each section has a symbol of its
+        // own after the null symbol.
+        symbols.sort_by(|a, b| (a.binding, a.value, &a.name).cmp(&(b.binding, b.value, &b.name)));
+        let mut all_symbols: Vec<Symbol> = (0..content_sections)
+            .map(|index| Symbol {
+                name: String::new(),
+                value: 0,
+                section: index as u16 + 1,
+                binding: STB_LOCAL,
+                kind: STT_SECTION,
+            })
+            .collect();
+        all_symbols.extend(symbols);
+        let first_global = all_symbols
+            .iter()
+            .position(|symbol| symbol.binding != STB_LOCAL)
+            .unwrap_or(all_symbols.len())
+            + 1;
+
+        for &target in &rel_targets {
+            let mut entries = Encoder::new(self.big_endian);
+            for (_, offset, symbol, kind) in
+                relocations.iter().filter(|(index, ..)| *index == target)
+            {
+                let index = all_symbols
+                    .iter()
+                    .position(|candidate| {
+                        candidate.kind != STT_SECTION && candidate.name == *symbol
+                    })
+                    .map_or(0, |index| index + 1);
+                entries.word(*offset);
+                entries.word(((index as u32) << 8) | kind);
+            }
+            let name = format!(".rel{}", sections[target].name);
+            let mut rel = Section::new(&name, SHT_REL, 0, 0, entries.bytes);
+            rel.link = symtab_index;
+            rel.info = target as u32 + 1;
+            rel.entry_size = REL_SIZE;
+            sections.push(rel);
+        }
+
+        let mut strings = vec![0];
+        let mut symtab = Encoder::new(self.big_endian);
+        symtab.pad_to(SYMBOL_SIZE as usize);
+        for symbol in &all_symbols {
+            let name = if symbol.name.is_empty() {
+                0
+            } else {
+                add_string(&mut strings, &symbol.name)
+            };
+            symtab.word(name);
+            symtab.word(symbol.value);
+            symtab.word(0);
+            symtab.bytes.push((symbol.binding << 4) | symbol.kind);
+            symtab.bytes.push(0);
+            symtab.half(symbol.section);
+        }
+        let mut symtab = Section::new(".symtab", SHT_SYMTAB, 0, 0, symtab.bytes);
+        symtab.link = symtab_index + 1;
+        symtab.info = first_global as u32;
+        symtab.entry_size = SYMBOL_SIZE;
+        sections.push(symtab);
+        let mut strtab = Section::new(".strtab", SHT_STRTAB, 0, 0, strings);
+        strtab.align = 1;
+        sections.push(strtab);
+
+        let mut section_names = vec![0];
+        let mut name_offsets: Vec<u32> = sections
+            .iter()
+            .map(|section| add_string(&mut section_names, &section.name))
+            .collect();
+        name_offsets.push(add_string(&mut section_names, ".shstrtab"));
+        let mut shstrtab = Section::new(".shstrtab", SHT_STRTAB, 0, 0, section_names);
+        shstrtab.align = 1;
+        sections.push(shstrtab);
+
+        // Executables load each allocated section as a segment of its own.
+        let segments: Vec<usize> = if file_type == ET_EXEC {
+            (0..content_sections)
+                .filter(|&index| sections[index].size > 0)
+                .collect()
+        } else {
+            vec![]
+        };
+        let mut offset = EHDR_SIZE + PHDR_SIZE * segments.len();
+        let offsets: Vec<usize> = sections
+            .iter()
+            .map(|section| {
+                offset = offset.next_multiple_of(section.align.max(1) as usize);
+                let start = offset;
+                offset += section.contents.len();
+                start
+            })
+            .collect();
+        let section_header_offset = offset.next_multiple_of(4);
+
+        let mut out = Encoder::new(self.big_endian);
+        out.bytes.extend_from_slice(b"\x7fELF");
+        out.bytes
+            .extend_from_slice(&[1, if self.big_endian { 2 } else { 1 }, 1]);
+        out.pad_to(16);
+        out.half(file_type);
+        out.half(EM_MIPS);
+        out.word(1);
+        out.word(entry);
+        out.word(if segments.is_empty() {
+            0
+        } else {
+            EHDR_SIZE as u32
+        });
+        out.word(section_header_offset as u32);
+        let arch = if self.mips64 {
+            EF_MIPS_ARCH_64R2
+        } else {
+            EF_MIPS_ARCH_32R2
+        };
+        out.word(arch | EF_MIPS_ABI_O32);
+        out.half(EHDR_SIZE as u16);
+        out.half(if segments.is_empty() {
+            0
+        } else {
+            PHDR_SIZE as u16
+        });
+        out.half(segments.len() as u16);
+        out.half(SHDR_SIZE as u16);
+        out.half(sections.len() as u16 + 1);
+        out.half(sections.len() as u16);
+
+        for &index in &segments {
+            let section = &sections[index];
+            let mut flags = PF_R;
+            if section.flags & SHF_WRITE != 0 {
+                flags |= PF_W;
+            }
+            if section.flags & SHF_EXECINSTR != 0 {
+                flags |= PF_X;
+            }
+            out.word(PT_LOAD);
+            out.word(offsets[index] as u32);
+            out.word(section.address);
+            out.word(section.address);
+            out.word(section.contents.len() as u32);
+            out.word(section.size);
+            out.word(flags);
+            out.word(section.align);
+        }
+        for (section, &start) in sections.iter().zip(&offsets) {
+            out.pad_to(start);
+            out.bytes.extend_from_slice(&section.contents);
+        }
+
+        out.pad_to(section_header_offset + SHDR_SIZE);
+        for ((section, &start), &name) in sections.iter().zip(&offsets).zip(&name_offsets) {
+            out.word(name);
+            out.word(section.kind);
+            out.word(section.flags);
+            out.word(section.address);
+            out.word(start as u32);
+            out.word(section.size);
+            out.word(section.link);
+            out.word(section.info);
+            out.word(section.align);
+            out.word(section.entry_size);
+        }
+        std::fs::write(filename, out.bytes)
+    }
+
+    // Replaces the loaded program with an ELF executable and starts it from
+    // the entry point. Executable segments become the program; the others
+    // are copied into memory.
+    pub fn load_elf(&mut self, filename: &str) {
+        let bytes = std::fs::read(filename).expect("Could not open file");
+        if let Err(message) = self.load_elf_bytes(&bytes) {
+            self.report_error(&format!("Could not load {}: {}", filename, message));
+        }
+    }
+
+    fn load_elf_bytes(&mut self, bytes: &[u8]) -> Result<(), String> {
+        if bytes.len() < EHDR_SIZE || bytes[..4] != *b"\x7fELF" {
+            return Err("not an ELF file".to_string());
+        }
+        if bytes[4] != 1 {
+            return Err("only 32-bit ELF files are supported".to_string());
+        }
+        let big_endian = match bytes[5] {
+            1 => false,
+            2 => true,
+            _ => return Err("unknown byte order".to_string()),
+        };
+        let reader = Reader { big_endian, bytes };
+        if reader.half(18)? != EM_MIPS {
+            return Err("not a MIPS file".to_string());
+        }
+        if reader.half(16)? != ET_EXEC {
+            return Err("not an executable; link object files before running them".to_string());
+        }
+        let entry = reader.word(24)?;
+        let program_header_offset = reader.word(28)? as usize;
+        let section_header_offset = reader.word(32)? as usize;
+        let program_header_size = reader.half(42)? as usize;
+        let program_headers = reader.half(44)? as usize;
+        let section_header_size = reader.half(46)? as usize;
+        let section_headers = reader.half(48)? as usize;
+
+        self.big_endian = big_endian;
+        self.program.clear();
+        self.program_sources.clear();
+        self.binary_program.clear();
+        self.instruction_index.clear();
+        self.units.clear();
+        self.listing.clear();
+        self.labels.clear();
+        for i in 0..program_headers {
+            let header = program_header_offset + i * program_header_size;
+            if reader.word(header)? != PT_LOAD {
+                continue;
+            }
+            let offset = reader.word(header + 4)? as usize;
+            let address = reader.word(header + 8)?;
+            let file_size = reader.word(header + 16)? as usize;
+            let memory_size = reader.word(header + 20)?;
+            let flags = reader.word(header + 24)?;
+            let contents = offset
+                .checked_add(file_size)
+                .and_then(|end| bytes.get(offset..end))
+                .ok_or("segment runs past the end of the file")?;
+            // Every address in the segment is below its end, so checking the
+            // end covers them all.
+            let end = address
+                .checked_add(memory_size.max(file_size as u32))
+                .ok_or("segment runs past the end of memory")?;
+            if flags & PF_X != 0 {
+                for (j, chunk) in contents.chunks_exact(4).enumerate() {
+                    let word = Reader {
+                        big_endian,
+                        bytes: chunk,
+                    }
+                    .word(0)?;
+                    let address = address + 4 * j as u32;
+                    self.instruction_index.insert(address, self.program.len());
+                    self.program.push((address, format!("0x{:08X}", word)));
+                    self.binary_program.push(word);
+                }
+            } else {
+                for (j, &byte) in contents.iter().enumerate() {
+                    self.write_byte(address + j as u32, byte);
+                }
+                self.next_free_address = self.next_free_address.max(end);
+            }
+        }
+
+        // Symbols are optional, but make memory dumps readable.
+        for i in 0..section_headers {
+            let header = section_header_offset + i * section_header_size;
+            if reader.word(header + 4)? != SHT_SYMTAB {
+                continue;
+            }
+            let offset = reader.word(header + 16)? as usize;
+            let size = reader.word(header + 20)? as usize;
+            let strtab_header =
+                section_header_offset + reader.word(header + 24)? as usize * section_header_size;
+            let strings = reader.word(strtab_header + 16)? as usize;
+            for symbol in (offset..offset + size)
+                .step_by(SYMBOL_SIZE as usize)
+                .skip(1)
+            {
+                let name = reader.string(strings + reader.word(symbol)? as usize);
+                let kind = *bytes.get(symbol + 12).ok_or("file is truncated")? & 0xF;
+                if !name.is_empty() && kind != STT_SECTION && reader.half(symbol + 14)? != SHN_UNDEF
+                {
+                    self.labels.insert(name, reader.word(symbol + 4)?);
+                }
+            }
+        }
+        self.pc = entry;
+        Ok(())
+    }
+}
@@ -0,0 +1,218 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+use crate::cp0::EXCEPTION_VECTOR;
+use crate::{MipsSimulator, DATA_BASE, TEXT_BASE};
+
+// Logisim writes runs of repeated values as `count*value`.
+const LOGISIM_MIN_RUN: usize = 4;
+const LOGISIM_VALUES_PER_LINE: usize = 8;
+const INTEL_HEX_RECORD_LENGTH: u32 = 16;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Segment {
+    Text,
+    Data,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DumpFormat {
+    Binary,
+    IntelHex,
+    ReadMemH,
+    ReadMemB,
+    Logisim,
+    HexText,
+    BinaryText,
+}
+
+impl DumpFormat {
+    pub fn from_name(name: &str) -> Option<DumpFormat> {
+        match name {
+            "binary" | "bin" => Some(DumpFormat::Binary),
+            "ihex" | "intel-hex" => Some(DumpFormat::IntelHex),
+            "readmemh" => Some(DumpFormat::ReadMemH),
+            "readmemb" => Some(DumpFormat::ReadMemB),
+            "logisim" => Some(DumpFormat::Logisim),
+            "hextext" => Some(DumpFormat::HexText),
+            "bintext" => Some(DumpFormat::BinaryText),
+            _ => None,
+        }
+    }
+}
+
+fn word_bytes(words: &[u32], big_endian: bool) -> Vec<u8> {
+    words
+        .iter()
+        .flat_map(|&word| {
+            if big_endian {
+                word.to_be_bytes()
+            } else {
+                word.to_le_bytes()
+            }
+        })
+        .collect()
+}
+
+// Data records of up to 16 bytes. An extended linear address record gives
+// the upper half of the address whenever it changes.
+fn intel_hex(bytes: &[u8], start: u32) -> String {
+    let mut out = String::new();
+    let mut record = |kind: u8, address: u16, data: &[u8]| {
+        let mut sum = data.len() as u8;
+        sum = sum
+            .wrapping_add((address >> 8) as u8)
+            .wrapping_add(address as u8);
+        sum = sum.wrapping_add(kind);
+        write!(out, ":{:02X}{:04X}{:02X}", data.len(), address, kind).unwrap();
+        for &byte in data {
+            write!(out, "{:02X}", byte).unwrap();
+            sum = sum.wrapping_add(byte);
+        }
+        writeln!(out, "{:02X}", sum.wrapping_neg()).unwrap();
+    };
+    let mut upper = 0;
+    let mut offset = 0;
+    while offset < bytes.len() {
+        let address = start + offset as u32;
+        if address >> 16 != upper {
+            upper = address >> 16;
+            record(4, 0, &(upper as u16).to_be_bytes());
+        }
+        // A record may not cross into the next 64 KiB.
+        let length = INTEL_HEX_RECORD_LENGTH
+            .min(0x1_0000 - (address & 0xFFFF))
+            .min((bytes.len() - offset) as u32) as usize;
+        record(0, address as u16, &bytes[offset..offset + length]);
+        offset += length;
+    }
+    record(1, 0, &[]);
+    out
+}
+
+fn logisim(words: &[u32]) -> String {
+    let mut values = vec![];
+    let mut i = 0;
+    while i < words.len() {
+        let run = words[i..]
+            .iter()
+            .take_while(|&&word| word == words[i])
+            .count();
+        if run >= LOGISIM_MIN_RUN {
+            values.push(format!("{}*{:x}", run, words[i]));
+            i += run;
+        } else {
+            values.push(format!("{:x}", words[i]));
+            i += 1;
+        }
+    }
+    let mut out = "v2.0 raw\n".to_string();
+    for line in values.chunks(LOGISIM_VALUES_PER_LINE) {
+        out.push_str(&line.join(" "));
+        out.push('\n');
+    }
+    out
+}
+
+// Renders words in the given format. `start` is where the first word goes in
+// the target memory: a byte address for Intel HEX, a word index for
+// $readmemh/$readmemb, and leading zero words for the formats that carry no
+// addresses.
+pub fn dump_words(words: &[u32], format: DumpFormat, big_endian: bool, start: u32) -> Vec<u8> {
+    let padded = || {
+        let mut padded = vec![0; start as usize / 4];
+        padded.extend_from_slice(words);
+        padded
+    };
+    let lines = |render: &dyn Fn(u32) -> String| -> String {
+        padded()
+            .into_iter()
+            .map(|word| render(word) + "\n")
+            .collect()
+    };
+    let text = match format {
+        DumpFormat::Binary => return word_bytes(&padded(), big_endian),
+        DumpFormat::IntelHex => intel_hex(&word_bytes(words, big_endian), start),
+        DumpFormat::ReadMemH | DumpFormat::ReadMemB => {
+            let mut out = format!("@{:x}\n", start / 4);
+            for &word in words {
+                if format == DumpFormat::ReadMemH {
+                    writeln!(out, "{:08x}", word).unwrap();
+                } else {
+                    writeln!(out, "{:032b}", word).unwrap();
+                }
+            }
+            out
+        }
+        DumpFormat::Logisim => logisim(&padded()),
+        DumpFormat::HexText => lines(&|word| format!("{:08x}", word)),
+        DumpFormat::BinaryText => lines(&|word| format!("{:032b}", word)),
+    };
+    text.into_bytes()
+}
+
+impl MipsSimulator {
+    // The words of the user text or data segment and the address of the
+    // first one. Data words are read in the simulator's byte order.
+    pub fn segment_words(&self, segment: Segment) -> (u32, Vec<u32>) {
+        match segment {
+            Segment::Text => {
+                let words: BTreeMap<u32, u32> = self
+                    .program
+                    .iter()
+                    .zip(&self.binary_program)
+                    .filter(|((address, _), _)| *address < EXCEPTION_VECTOR)
+                    .map(|(&(address, _), &word)| (address, word))
+                    .collect();
+                let Some(&start) = words.keys().next() else {
+                    return (TEXT_BASE, vec![]);
+                };
+                let mut dense = vec![];
+                for (address, word) in words {
+                    dense.resize(((address - start) / 4) as usize, 0);
+                    dense.push(word);
+                }
+                (start, dense)
+            }
+            Segment::Data => {
+                let end = self.next_free_address.next_multiple_of(4);
+                let words = (DATA_BASE..end)
+                    .step_by(4)
+                    .map(|address| self.read_word(address))
+                    .collect();
+                (DATA_BASE, words)
+            }
+        }
+    }
+
+    // Dumps a segment for a target memory whose first location is `base`,
+    // which defaults to the start of the segment. Data is laid out in bytes
+    // by the assembler, so it can only be dumped in the byte order it was
+    // assembled in.
+    pub fn dump_segment(
+        &self,
+        segment: Segment,
+        format: DumpFormat,
+        big_endian: bool,
+        base: Option<u32>,
+    ) -> Result<Vec<u8>, String> {
+        if segment == Segment::Data && big_endian != self.big_endian {
+            let order = |big| if big { "big" } else { "little" };
+            return Err(format!(
+                "Data was assembled {}-endian and cannot be dumped {}-endian; \
+                 assemble with the byte order the dump needs",
+                order(self.big_endian),
+                order(big_endian)
+            ));
+        }
+        let (start, words) = self.segment_words(segment);
+        let base = base.unwrap_or(start);
+        match start.checked_sub(base) {
+            Some(offset) if offset % 4 == 0 => Ok(dump_words(&words, format, big_endian, offset)),
+            _ => Err(format!(
+                "Base address 0x{:08X} must be word aligned and at most 0x{:08X}",
+                base, start
+            )),
+        }
+    }
+}
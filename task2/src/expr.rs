@@ -0,0 +1,408 @@
+use crate::MipsSimulator;
+
+const OPERATOR_CHARS: &str = "+-*/&|<>";
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Number(i64),
+    Symbol(String),
+    Operator(&'static str),
+    Open,
+    Close,
+}
+
+// Reads a character literal body such as `A`, `\n` or `\''`, returning the
+// character's value and the number of bytes it used.
+fn character_literal(text: &str) -> Option<(i64, usize)> {
+    let mut chars = text.chars();
+    let (value, length) = match chars.next()? {
+        '\\' => {
+            let escaped = chars.next()?;
+            let value = match escaped {
+                'n' => '\n',
+                't' => '\t',
+                'r' => '\r',
+                '0' => '\0',
+                c => c,
+            };
+            (value as i64, 1 + escaped.len_utf8())
+        }
+        c => (c as i64, c.len_utf8()),
+    };
+    (chars.next()? == '\'').then_some((value, length + 1))
+}
+
+// Decimal, 0x hex, 0b binary, and C-style octal with a leading 0 or 0o.
+fn number_literal(text: &str) -> Result<i64, String> {
+    let lower = text.to_ascii_lowercase();
+    let parsed = if let Some(digits) = lower.strip_prefix("0x") {
+        u64::from_str_radix(digits, 16)
+    } else if let Some(digits) = lower.strip_prefix("0b") {
+        u64::from_str_radix(digits, 2)
+    } else if let Some(digits) = lower.strip_prefix("0o") {
+        u64::from_str_radix(digits, 8)
+    } else if lower.len() > 1 && lower.starts_with('0') {
+        u64::from_str_radix(&lower[1..], 8)
+    } else {
+        lower.parse::<u64>()
+    };
+    parsed
+        .map(|value| value as i64)
+        .map_err(|_| format!("Invalid number {}", text))
+}
+
+fn tokenize(expression: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = vec![];
+    let mut rest = expression.trim_start();
+    while let Some(c) = rest.chars().next() {
+        let length = if c == '\'' {
+            let (value, length) = character_literal(&rest[1..])
+                .ok_or_else(|| format!("Invalid character literal in {}", expression))?;
+            tokens.push(Token::Number(value));
+            length + 1
+        } else if c == '(' || c == ')' {
+            tokens.push(if c == '(' { Token::Open } else { Token::Close });
+            1
+        } else if let Some(operator) = ["<<", ">>", "+", "-", "*", "/", "&", "|", "~"]
+            .into_iter()
+            .find(|operator| rest.starts_with(operator))
+        {
+            tokens.push(Token::Operator(operator));
+            operator.len()
+        } else if c.is_ascii_alphanumeric() || c == '_' || c == '.' || c == '%' {
+            let length = rest[1..]
+                .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_' || c == '.'))
+                .map_or(rest.len(), |end| end + 1);
+            let word = &rest[..length];
+            tokens.push(if c.is_ascii_digit() {
+                Token::Number(number_literal(word)?)
+            } else {
+                Token::Symbol(word.to_string())
+            });
+            length
+        } else {
+            return Err(format!("Unexpected '{}' in {}", c, expression));
+        };
+        rest = rest[length..].trim_start();
+    }
+    Ok(tokens)
+}
+
+// A recursive-descent evaluator. From loosest to tightest binding the levels
+// are |, &, shifts, + and -, * and /, and finally unary operators.
+struct Parser<'a> {
+    tokens: Vec<Token>,
+    position: usize,
+    lookup: &'a dyn Fn(&str) -> Option<i64>,
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.position).cloned();
+        self.position += 1;
+        token
+    }
+
+    fn binary(&mut self, level: usize) -> Result<i64, String> {
+        const LEVELS: [&[&str]; 5] = [&["|"], &["&"], &["<<", ">>"], &["+", "-"], &["*", "/"]];
+        if level == LEVELS.len() {
+            return self.unary();
+        }
+        let mut value = self.binary(level + 1)?;
+        while let Some(&Token::Operator(operator)) = self.peek() {
+            if !LEVELS[level].contains(&operator) {
+                break;
+            }
+            self.position += 1;
+            let right = self.binary(level + 1)?;
+            value = match operator {
+                "|" => value | right,
+                "&" => value & right,
+                "<<" => value.wrapping_shl(right as u32),
+                ">>" => value.wrapping_shr(right as u32),
+                "+" => value.wrapping_add(right),
+                "-" => value.wrapping_sub(right),
+                "*" => value.wrapping_mul(right),
+                _ => value
+                    .checked_div(right)
+                    .ok_or_else(|| "Division by zero".to_string())?,
+            };
+        }
+        Ok(value)
+    }
+
+    fn unary(&mut self) -> Result<i64, String> {
+        match self.next() {
+            Some(Token::Operator("-")) => Ok(self.unary()?.wrapping_neg()),
+            Some(Token::Operator("+")) => self.unary(),
+            Some(Token::Operator("~")) => Ok(!self.unary()?),
+            Some(Token::Number(value)) => Ok(value),
+            Some(Token::Open) => self.parenthesised(),
+            // %hi rounds up when %lo will be sign-extended negative, so that
+            // lui %hi followed by addiu %lo rebuilds the full value.
+            Some(Token::Symbol(name)) if name == "%hi" || name == "%lo" => {
+                if self.next() != Some(Token::Open) {
+                    return Err(format!("Expected ( after {}", name));
+                }
+                let value = self.parenthesised()?;
+                Ok(if name == "%hi" {
+                    (value.wrapping_add(0x8000) >> 16) & 0xFFFF
+                } else {
+                    value as i16 as i64
+                })
+            }
+            Some(Token::Symbol(name)) => {
+                (self.lookup)(&name).ok_or_else(|| format!("Undefined symbol {}", name))
+            }
+            _ => Err("Incomplete expression".to_string()),
+        }
+    }
+
+    fn parenthesised(&mut self) -> Result<i64, String> {
+        let value = self.binary(0)?;
+        match self.next() {
+            Some(Token::Close) => Ok(value),
+            _ => Err("Missing )".to_string()),
+        }
+    }
+}
+
+// The symbols an expression refers to, other than %hi and %lo.
+pub fn referenced_symbols(expression: &str) -> Vec<String> {
+    tokenize(expression)
+        .unwrap_or_default()
+        .into_iter()
+        .filter_map(|token| match token {
+            Token::Symbol(name) if name != "%hi" && name != "%lo" => Some(name),
+            _ => None,
+        })
+        .collect()
+}
+
+pub fn evaluate(expression: &str, lookup: &dyn Fn(&str) -> Option<i64>) -> Result<i64, String> {
+    let mut parser = Parser {
+        tokens: tokenize(expression)?,
+        position: 0,
+        lookup,
+    };
+    let value = parser.binary(0)?;
+    match parser.peek() {
+        None => Ok(value),
+        Some(_) => Err(format!("Unexpected trailing input in {}", expression)),
+    }
+}
+
+// Splits operands on commas outside quotes and parentheses. Without any
+// commas, whitespace separates operands unless an operator joins its
+// neighbours, so both `.word 1 2 3` and `.word 1, arr + 4` work. A sign
+// written against its number, as in `.word 5 -3`, starts a new operand.
+pub fn split_operands(text: &str) -> Vec<String> {
+    let mut pieces = vec![];
+    let mut current = String::new();
+    let mut depth = 0;
+    let mut in_quote = None;
+    let mut escaped = false;
+    for c in text.chars() {
+        match in_quote {
+            Some(quote) => {
+                if escaped {
+                    escaped = false;
+                } else if c == '\\' {
+                    escaped = true;
+                } else if c == quote {
+                    in_quote = None;
+                }
+            }
+            None => match c {
+                '\'' | '"' => in_quote = Some(c),
+                '(' => depth += 1,
+                ')' => depth -= 1,
+                ',' if depth == 0 => {
+                    pieces.push(std::mem::take(&mut current));
+                    continue;
+                }
+                _ => {}
+            },
+        }
+        current.push(c);
+    }
+    pieces.push(current);
+    if pieces.len() > 1 {
+        return pieces
+            .iter()
+            .map(|piece| piece.trim().to_string())
+            .filter(|piece| !piece.is_empty())
+            .collect();
+    }
+
+    let mut operands: Vec<String> = vec![];
+    let mut joining = false;
+    for word in split_words(pieces[0].trim()) {
+        let signed = word.len() > 1 && word.starts_with(['+', '-']);
+        let starts_with_operator = word.starts_with(|c| OPERATOR_CHARS.contains(c));
+        match operands.last_mut() {
+            Some(last) if joining || (starts_with_operator && !signed) => {
+                last.push(' ');
+                last.push_str(word);
+            }
+            _ => operands.push(word.to_string()),
+        }
+        joining = word.ends_with(|c| OPERATOR_CHARS.contains(c));
+    }
+    operands
+}
+
+// Whitespace-separated words, keeping quoted text such as ' ' in one word.
+fn split_words(text: &str) -> Vec<&str> {
+    let mut words = vec![];
+    let mut start = None;
+    let mut in_quote = None;
+    let mut escaped = false;
+    for (i, c) in text.char_indices() {
+        if let Some(quote) = in_quote {
+            if escaped {
+                escaped = false;
+            } else if c == '\\' {
+                escaped = true;
+            } else if c == quote {
+                in_quote = None;
+            }
+            continue;
+        }
+        if c.is_whitespace() {
+            if let Some(begin) = start.take() {
+                words.push(&text[begin..i]);
+            }
+            continue;
+        }
+        start.get_or_insert(i);
+        if c == '\'' || c == '"' {
+            in_quote = Some(c);
+        }
+    }
+    if let Some(begin) = start {
+        words.push(&text[begin..]);
+    }
+    words
+}
+
+// Drops a `#` comment and everything after it, unless the `#` is quoted as
+// in `.asciiz "#1"` or `'#'`.
+pub fn strip_comment(line: &str) -> &str {
+    let mut in_quote = None;
+    let mut escaped = false;
+    for (i, c) in line.char_indices() {
+        match in_quote {
+            Some(quote) => {
+                if escaped {
+                    escaped = false;
+                } else if c == '\\' {
+                    escaped = true;
+                } else if c == quote {
+                    in_quote = None;
+                }
+            }
+            None if c == '\'' || c == '"' => in_quote = Some(c),
+            None if c == '#' => return &line[..i],
+            None => {}
+        }
+    }
+    line
+}
+
+// Splits a memory operand into its offset and optional base register:
+// `expr($base)`, `($base)`, or a bare `expr` for an absolute address.
+pub fn split_memory_operand(operand: &str) -> (&str, Option<&str>) {
+    let operand = operand.trim();
+    if let Some(without_close) = operand.strip_suffix(')') {
+        if let Some(open) = without_close.rfind('(') {
+            let base = without_close[open + 1..].trim();
+            if base.starts_with('$') {
+                return (without_close[..open].trim(), Some(base));
+            }
+        }
+    }
+    (operand, None)
+}
+
+// Splits an instruction into its mnemonic followed by its operands.
+pub fn split_instruction(instruction: &str) -> Vec<String> {
+    let instruction = instruction.trim();
+    let (opcode, operands) = instruction
+        .split_once(char::is_whitespace)
+        .unwrap_or((instruction, ""));
+    let mut parts = vec![opcode.to_string()];
+    parts.extend(split_operands(operands));
+    parts
+}
+
+impl MipsSimulator {
+    // .eqv/.set constants take precedence over labels of the same name.
+    pub fn symbol_value(&self, name: &str) -> Option<i64> {
+        self.constants
+            .get(name)
+            .copied()
+            .or_else(|| self.labels.get(name).map(|&address| address as i64))
+    }
+
+    pub fn try_evaluate(&self, expression: &str) -> Result<i64, String> {
+        evaluate(expression, &|name| self.symbol_value(name))
+    }
+
+    pub fn immediate_operand(&self, expression: &str) -> i64 {
+        self.try_evaluate(expression).unwrap_or_else(|message| {
+            self.report_error(&message);
+            0
+        })
+    }
+
+    // A 16-bit immediate field. Signed fields hold -32768..=32767 and
+    // unsigned ones 0..=65535; a value outside the range is reported and
+    // encoded as 0 rather than truncated.
+    pub fn immediate_field(&self, expression: &str, signed: bool) -> u32 {
+        let value = self.immediate_operand(expression);
+        let (range, kind) = if signed {
+            (-0x8000..=0x7FFF, "signed")
+        } else {
+            (0..=0xFFFF, "unsigned")
+        };
+        if !range.contains(&value) {
+            self.report_error(&format!(
+                "{} does not fit in a {} 16-bit immediate",
+                value, kind
+            ));
+            return 0;
+        }
+        value as u32 & 0xFFFF
+    }
+
+    // An unsigned field of the given width, such as a shift amount. A value
+    // that does not fit is reported and encoded as 0.
+    pub fn unsigned_field(&self, expression: &str, bits: u32, what: &str) -> u32 {
+        let value = self.immediate_operand(expression);
+        let max = (1 << bits) - 1;
+        if !(0..=max).contains(&value) {
+            self.report_error(&format!(
+                "{} {} is out of range (0 to {})",
+                what, value, max
+            ));
+            return 0;
+        }
+        value as u32
+    }
+
+    // The encoded offset and the base register of a memory operand.
+    pub fn memory_operand(&self, operand: &str) -> (u32, Option<usize>) {
+        let (offset, base) = split_memory_operand(operand);
+        let offset = if offset.is_empty() {
+            0
+        } else {
+            self.immediate_field(offset, true)
+        };
+        (offset, base.map(|base| self.get_register_index(base)))
+    }
+}
@@ -0,0 +1,333 @@
+use std::collections::HashMap;
+
+use crate::cp0::Exception;
+use crate::{DecodedInstruction, MipsSimulator};
+
+pub const COP1: u32 = 17;
+const FMT_S: u32 = 16;
+const FMT_D: u32 = 17;
+const FMT_W: u32 = 20;
+
+pub fn is_fpu_instruction(opcode: &str) -> bool {
+    match opcode {
+        "mfc1" | "mtc1" | "lwc1" | "swc1" | "ldc1" | "sdc1" | "bc1t" | "bc1f" => true,
+        _ => fpu_format(opcode).is_some(),
+    }
+}
+
+// Splits "add.s" into ("add", FMT_S), "cvt.d.w" into ("cvt.d", FMT_W) and so on.
+fn fpu_format(opcode: &str) -> Option<(&str, u32)> {
+    let (base, suffix) = opcode.rsplit_once('.')?;
+    let fmt = match suffix {
+        "s" => FMT_S,
+        "d" => FMT_D,
+        "w" => FMT_W,
+        _ => return None,
+    };
+    let valid = match base {
+        "add" | "sub" | "mul" | "div" | "sqrt" | "abs" | "mov" | "neg" | "c.eq" | "c.lt"
+        | "c.le" => fmt != FMT_W,
+        "cvt.s" => fmt != FMT_S,
+        "cvt.d" => fmt != FMT_D,
+        "cvt.w" => fmt != FMT_W,
+        _ => false,
+    };
+    if valid {
+        Some((base, fmt))
+    } else {
+        None
+    }
+}
+
+impl MipsSimulator {
+    pub fn assemble_fpu_instruction(
+        &self,
+        opcode: &str,
+        parts: &[String],
+        current_address: u32,
+    ) -> u32 {
+        match opcode {
+            "mfc1" | "mtc1" => {
+                let rs = if opcode == "mfc1" { 0 } else { 4 };
+                let rt = self.get_register_index(&parts[1]) as u32;
+                let fs = self.get_fp_register_index(&parts[2]) as u32;
+                (COP1 << 26) | (rs << 21) | (rt << 16) | (fs << 11)
+            }
+            "lwc1" | "swc1" | "ldc1" | "sdc1" => {
+                let opcode_map =
+                    HashMap::from([("lwc1", 49), ("ldc1", 53), ("swc1", 57), ("sdc1", 61)]);
+                let opcode_num = *opcode_map.get(opcode).unwrap();
+                let ft = self.get_fp_register_index(&parts[1]) as u32;
+                let (offset, base) = self.memory_operand(&parts[2]);
+                let rs = base.unwrap_or(0) as u32;
+                (opcode_num << 26) | (rs << 21) | (ft << 16) | offset
+            }
+            "bc1t" | "bc1f" => {
+                // `bc1t label` tests condition flag 0; `bc1t cc, label` names the flag.
+                let tf = if opcode == "bc1t" { 1 } else { 0 };
+                let (cc, label) = if parts.len() > 2 {
+                    (self.immediate_operand(&parts[1]) as u32 & 7, &parts[2])
+                } else {
+                    (0, &parts[1])
+                };
+                match self.branch_offset(label, current_address) {
+                    Ok(offset) => (COP1 << 26) | (8 << 21) | (cc << 18) | (tf << 16) | offset,
+                    Err(message) => {
+                        self.report_error(&message);
+                        0
+                    }
+                }
+            }
+            _ => {
+                let (base, fmt) = fpu_format(opcode).unwrap();
+                match base {
+                    "c.eq" | "c.lt" | "c.le" => {
+                        let funct_map = HashMap::from([("c.eq", 50), ("c.lt", 60), ("c.le", 62)]);
+                        let funct = *funct_map.get(base).unwrap();
+                        let (cc, operands) = if parts.len() > 3 {
+                            (self.immediate_operand(&parts[1]) as u32 & 7, &parts[2..])
+                        } else {
+                            (0, &parts[1..])
+                        };
+                        let fs = self.get_fp_register_index(&operands[0]) as u32;
+                        let ft = self.get_fp_register_index(&operands[1]) as u32;
+                        (COP1 << 26) | (fmt << 21) | (ft << 16) | (fs << 11) | (cc << 8) | funct
+                    }
+                    "add" | "sub" | "mul" | "div" => {
+                        let funct_map =
+                            HashMap::from([("add", 0), ("sub", 1), ("mul", 2), ("div", 3)]);
+                        let funct = *funct_map.get(base).unwrap();
+                        let fd = self.get_fp_register_index(&parts[1]) as u32;
+                        let fs = self.get_fp_register_index(&parts[2]) as u32;
+                        let ft = self.get_fp_register_index(&parts[3]) as u32;
+                        (COP1 << 26) | (fmt << 21) | (ft << 16) | (fs << 11) | (fd << 6) | funct
+                    }
+                    _ => {
+                        let funct_map = HashMap::from([
+                            ("sqrt", 4),
+                            ("abs", 5),
+                            ("mov", 6),
+                            ("neg", 7),
+                            ("cvt.s", 32),
+                            ("cvt.d", 33),
+                            ("cvt.w", 36),
+                        ]);
+                        let funct = *funct_map.get(base).unwrap();
+                        let fd = self.get_fp_register_index(&parts[1]) as u32;
+                        let fs = self.get_fp_register_index(&parts[2]) as u32;
+                        (COP1 << 26) | (fmt << 21) | (fs << 11) | (fd << 6) | funct
+                    }
+                }
+            }
+        }
+    }
+
+    pub fn get_fp_register_index(&self, register: &str) -> usize {
+        match register
+            .strip_prefix("$f")
+            .and_then(|n| n.parse::<usize>().ok())
+        {
+            Some(index) if index < 32 => index,
+            _ => {
+                self.report_error(&format!("Unknown FPU register {}", register));
+                0
+            }
+        }
+    }
+
+    // Returns true when the instruction has already updated the program counter.
+    pub fn execute_cop1(&mut self, decoded: DecodedInstruction) -> bool {
+        match decoded.rs as u32 {
+            0 => self.registers[decoded.rt as usize] = self.fpr[decoded.rd as usize] as i32 as i64,
+            4 => self.fpr[decoded.rd as usize] = self.registers[decoded.rt as usize] as u32,
+            8 => {
+                self.execute_bc1(decoded);
+                return true;
+            }
+            FMT_S => self.execute_fpu_single(decoded),
+            FMT_D => self.execute_fpu_double(decoded),
+            FMT_W => self.execute_fpu_word(decoded),
+            _ => self.raise_exception(Exception::ReservedInstruction),
+        }
+        false
+    }
+
+    // In the FPU-specific fields, fs lives where rd does, fd where shamt does.
+    fn execute_fpu_single(&mut self, decoded: DecodedInstruction) {
+        let fs = self.read_single(decoded.rd as usize);
+        let ft = self.read_single(decoded.rt as usize);
+        let fd = decoded.shamt as usize;
+        match decoded.funct {
+            0 => self.write_single(fd, fs + ft),
+            1 => self.write_single(fd, fs - ft),
+            2 => self.write_single(fd, fs * ft),
+            3 => self.write_single(fd, fs / ft),
+            4 => self.write_single(fd, fs.sqrt()),
+            5 => self.write_single(fd, fs.abs()),
+            6 => self.fpr[fd] = self.fpr[decoded.rd as usize],
+            7 => self.write_single(fd, -fs),
+            33 => self.write_double(fd, fs as f64),
+            36 => self.fpr[fd] = self.round_to_word(fs as f64) as u32,
+            48..=63 => self.set_condition(decoded, fs.partial_cmp(&ft)),
+            _ => self.raise_exception(Exception::ReservedInstruction),
+        }
+    }
+
+    fn execute_fpu_double(&mut self, decoded: DecodedInstruction) {
+        let fs = self.read_double(decoded.rd as usize);
+        let ft = self.read_double(decoded.rt as usize);
+        let fd = decoded.shamt as usize;
+        match decoded.funct {
+            0 => self.write_double(fd, fs + ft),
+            1 => self.write_double(fd, fs - ft),
+            2 => self.write_double(fd, fs * ft),
+            3 => self.write_double(fd, fs / ft),
+            4 => self.write_double(fd, fs.sqrt()),
+            5 => self.write_double(fd, fs.abs()),
+            6 => self.write_double(fd, fs),
+            7 => self.write_double(fd, -fs),
+            32 => self.write_single(fd, fs as f32),
+            36 => self.fpr[fd] = self.round_to_word(fs) as u32,
+            48..=63 => self.set_condition(decoded, fs.partial_cmp(&ft)),
+            _ => self.raise_exception(Exception::ReservedInstruction),
+        }
+    }
+
+    fn execute_fpu_word(&mut self, decoded: DecodedInstruction) {
+        let fs = self.fpr[decoded.rd as usize] as i32;
+        let fd = decoded.shamt as usize;
+        match decoded.funct {
+            32 => self.write_single(fd, fs as f32),
+            33 => self.write_double(fd, fs as f64),
+            _ => self.raise_exception(Exception::ReservedInstruction),
+        }
+    }
+
+    // c.cond.fmt: the low four funct bits select the relations that make the
+    // condition true (bit 0 unordered, bit 1 equal, bit 2 less than).
+    fn set_condition(&mut self, decoded: DecodedInstruction, ordering: Option<std::cmp::Ordering>) {
+        let cond = decoded.funct & 0xF;
+        let result = match ordering {
+            None => cond & 1 != 0,
+            Some(std::cmp::Ordering::Equal) => cond & 2 != 0,
+            Some(std::cmp::Ordering::Less) => cond & 4 != 0,
+            Some(std::cmp::Ordering::Greater) => false,
+        };
+        let cc = (decoded.shamt >> 2) as u32;
+        let bit = condition_bit(cc);
+        if result {
+            self.fcsr |= bit;
+        } else {
+            self.fcsr &= !bit;
+        }
+    }
+
+    fn execute_bc1(&mut self, decoded: DecodedInstruction) {
+        let cc = (decoded.rt >> 2) as u32;
+        let on_true = decoded.rt & 1 != 0;
+        let flag = self.fcsr & condition_bit(cc) != 0;
+        self.branch(flag == on_true, decoded);
+    }
+
+    // Converts to a 32-bit integer using the FCSR rounding mode. Values that
+    // do not fit produce the architecture's default invalid result.
+    fn round_to_word(&self, value: f64) -> i32 {
+        let rounded = match self.fcsr & 3 {
+            0 => value.round_ties_even(),
+            1 => value.trunc(),
+            2 => value.ceil(),
+            _ => value.floor(),
+        };
+        if rounded.is_nan() || rounded < i32::MIN as f64 || rounded > i32::MAX as f64 {
+            i32::MAX
+        } else {
+            rounded as i32
+        }
+    }
+
+    pub fn execute_lwc1(&mut self, decoded: DecodedInstruction) {
+        let address = self.effective_address(&decoded);
+        if let Some(address) = self.check_address(address, 4, false) {
+            self.fpr[decoded.rt as usize] = self.read_word(address);
+        }
+    }
+
+    pub fn execute_swc1(&mut self, decoded: DecodedInstruction) {
+        let address = self.effective_address(&decoded);
+        if let Some(address) = self.check_address(address, 4, true) {
+            self.write_word(address, self.fpr[decoded.rt as usize]);
+        }
+    }
+
+    pub fn execute_ldc1(&mut self, decoded: DecodedInstruction) {
+        let address = self.effective_address(&decoded);
+        if let Some(address) = self.check_address(address, 8, false) {
+            let bits = self.read_doubleword(address);
+            self.write_double(decoded.rt as usize, f64::from_bits(bits));
+        }
+    }
+
+    pub fn execute_sdc1(&mut self, decoded: DecodedInstruction) {
+        let address = self.effective_address(&decoded);
+        if let Some(address) = self.check_address(address, 8, true) {
+            let bits = self.read_double(decoded.rt as usize).to_bits();
+            self.write_doubleword(address, bits);
+        }
+    }
+
+    fn read_single(&self, index: usize) -> f32 {
+        f32::from_bits(self.fpr[index])
+    }
+
+    fn write_single(&mut self, index: usize, value: f32) {
+        self.fpr[index] = value.to_bits();
+    }
+
+    // Doubles occupy an even/odd register pair, low word in the even register.
+    fn read_double(&self, index: usize) -> f64 {
+        let index = index & !1;
+        f64::from_bits(((self.fpr[index + 1] as u64) << 32) | self.fpr[index] as u64)
+    }
+
+    fn write_double(&mut self, index: usize, value: f64) {
+        let index = index & !1;
+        let bits = value.to_bits();
+        self.fpr[index] = bits as u32;
+        self.fpr[index + 1] = (bits >> 32) as u32;
+    }
+
+    pub fn print_fpu_registers(&self) {
+        println!("FPU registers:");
+        for i in 0..32 {
+            if i % 2 == 0 {
+                println!(
+                    "$f{:<2}: 0x{:08X}  float {:<14} double {}",
+                    i,
+                    self.fpr[i],
+                    self.read_single(i),
+                    self.read_double(i)
+                );
+            } else {
+                println!(
+                    "$f{:<2}: 0x{:08X}  float {}",
+                    i,
+                    self.fpr[i],
+                    self.read_single(i)
+                );
+            }
+        }
+        let flags: Vec<String> = (0..8)
+            .map(|cc| ((self.fcsr & condition_bit(cc) != 0) as u8).to_string())
+            .collect();
+        println!("$fcsr: 0x{:08X}  cc[0..7] {}", self.fcsr, flags.join(" "));
+    }
+}
+
+// Condition flag 0 is FCSR bit 23; flags 1-7 occupy bits 25-31.
+fn condition_bit(cc: u32) -> u32 {
+    if cc == 0 {
+        1 << 23
+    } else {
+        1 << (24 + cc)
+    }
+}
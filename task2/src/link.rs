@@ -0,0 +1,191 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::ops::Range;
+use std::path::Path;
+
+use crate::expr::{
+    referenced_symbols, split_instruction, split_memory_operand, split_operands, strip_comment,
+};
+use crate::macros::SourceLine;
+use crate::MipsSimulator;
+
+// Deeper nesting than this is taken to be a file that includes itself.
+const MAX_INCLUDE_DEPTH: usize = 16;
+
+// One separately assembled source file. Its labels are private to it unless
+// named by .globl, and .extern lists the names it expects another unit to
+// define. Symbol declarations remember where they were made, for errors.
+// When writing objects, names that nothing defines are left `undefined` for
+// a later link.
+#[derive(Default)]
+pub struct ObjectUnit {
+    pub name: String,
+    pub labels: HashMap<String, u32>,
+    pub constants: HashMap<String, i64>,
+    pub globals: BTreeMap<String, String>,
+    pub externs: BTreeMap<String, String>,
+    // The unit's instructions within the simulator's program.
+    pub program: Range<usize>,
+    pub data: Range<u32>,
+    pub data_fixups: Vec<(u32, u32, String, String)>,
+    pub undefined: BTreeSet<String>,
+}
+
+impl ObjectUnit {
+    // Records `.globl a, b` or `.extern name size`. Returns false for any
+    // other line.
+    pub fn declare_symbols(&mut self, line: &str, location: &str) -> bool {
+        let (directive, arguments) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+        let names = split_operands(arguments);
+        match directive {
+            ".globl" | ".global" => {
+                for name in names {
+                    self.globals.insert(name, location.to_string());
+                }
+            }
+            // MARS gives .extern a size as well, which needs no storage here.
+            ".extern" => {
+                if let Some(name) = names.into_iter().next() {
+                    self.externs.insert(name, location.to_string());
+                }
+            }
+            _ => return false,
+        }
+        true
+    }
+}
+
+impl MipsSimulator {
+    // Splits a file into lines without their comments, replacing each
+    // `.include "file"` with the lines of that file. Included paths are
+    // relative to the including file.
+    pub fn read_source(&self, filename: &str, contents: &str, depth: usize) -> Vec<SourceLine> {
+        let mut lines = vec![];
+        for (i, text) in contents.lines().enumerate() {
+            let text = strip_comment(text);
+            let location = format!("{}:{}", filename, i + 1);
+            let Some(argument) = text.trim().strip_prefix(".include") else {
+                lines.push(SourceLine {
+                    text: text.to_string(),
+                    line: i + 1,
+                    location,
+                });
+                continue;
+            };
+            let directory = Path::new(filename).parent().unwrap_or(Path::new(""));
+            let path = directory.join(argument.trim().trim_matches('"'));
+            if depth == MAX_INCLUDE_DEPTH {
+                self.report_error_at(
+                    &format!(
+                        "{} is included too deeply; does it include itself?",
+                        path.display()
+                    ),
+                    &location,
+                );
+                continue;
+            }
+            match std::fs::read_to_string(&path) {
+                Ok(included) => {
+                    lines.extend(self.read_source(&path.to_string_lossy(), &included, depth + 1))
+                }
+                Err(error) => self.report_error_at(
+                    &format!("Could not include {}: {}", path.display(), error),
+                    &location,
+                ),
+            }
+        }
+        lines
+    }
+
+    // Resolves .globl and .extern across the loaded units, then assembles each
+    // unit against its own labels and every global. Undefined and duplicate
+    // globals are reported and nothing is assembled.
+    pub fn link(&mut self) {
+        let mut units = std::mem::take(&mut self.units);
+        let mut globals: HashMap<String, (u32, &str)> = HashMap::new();
+        let mut linked = true;
+        for unit in &units {
+            for (name, location) in &unit.globals {
+                let Some(&address) = unit.labels.get(name) else {
+                    self.report_error_at(
+                        &format!("Global symbol {} is not defined in {}", name, unit.name),
+                        location,
+                    );
+                    linked = false;
+                    continue;
+                };
+                if let Some((_, other)) = globals.insert(name.clone(), (address, &unit.name)) {
+                    self.report_error_at(
+                        &format!(
+                            "Duplicate global symbol {} (also defined in {})",
+                            name, other
+                        ),
+                        location,
+                    );
+                    linked = false;
+                }
+            }
+        }
+        for unit in &units {
+            for (name, location) in &unit.externs {
+                if !self.objects && !globals.contains_key(name) && !unit.labels.contains_key(name) {
+                    self.report_error_at(
+                        &format!("Undefined symbol {} declared .extern", name),
+                        location,
+                    );
+                    linked = false;
+                }
+            }
+        }
+
+        let globals: HashMap<String, u32> = globals
+            .into_iter()
+            .map(|(name, (address, _))| (name, address))
+            .collect();
+        if linked {
+            for unit in &mut units {
+                self.labels = globals.clone();
+                self.labels.extend(unit.labels.clone());
+                self.constants = unit.constants.clone();
+                // Undefined names assemble as zero; the relocations recorded
+                // against them tell the linker what to fill in.
+                if self.objects {
+                    unit.undefined = self.undefined_names(unit);
+                    self.labels
+                        .extend(unit.undefined.iter().map(|name| (name.clone(), 0)));
+                }
+                self.undefined_symbols = unit.undefined.clone();
+                self.data_fixups = unit.data_fixups.clone();
+                self.resolve_data_fixups();
+                self.assemble_instructions(unit.program.clone());
+            }
+        }
+
+        // Afterwards every label is visible, globals winning over any local
+        // of the same name in another unit.
+        self.labels = units
+            .iter()
+            .flat_map(|unit| unit.labels.clone())
+            .chain(globals)
+            .collect();
+        self.units = units;
+        self.undefined_symbols.clear();
+    }
+
+    // The names a unit's instructions and data refer to that neither it nor
+    // any global defines.
+    fn undefined_names(&self, unit: &ObjectUnit) -> BTreeSet<String> {
+        let instructions = self.program[unit.program.clone()]
+            .iter()
+            .flat_map(|(_, instruction)| split_instruction(instruction).into_iter().skip(1))
+            .map(|operand| split_memory_operand(&operand).0.to_string());
+        let data = unit
+            .data_fixups
+            .iter()
+            .map(|(_, _, expression, _)| expression.clone());
+        instructions
+            .chain(data)
+            .flat_map(|expression| referenced_symbols(&expression))
+            .filter(|name| self.symbol_value(name).is_none())
+            .collect()
+    }
+}
@@ -0,0 +1,216 @@
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Write};
+use std::path::{Component, Path, PathBuf};
+
+use crate::MipsSimulator;
+
+// o32 syscall numbers are offset by 4000.
+const SYS_EXIT: i64 = 4001;
+const SYS_READ: i64 = 4003;
+const SYS_WRITE: i64 = 4004;
+const SYS_CLOSE: i64 = 4006;
+const SYS_BRK: i64 = 4045;
+const SYS_MMAP: i64 = 4090;
+const SYS_UNAME: i64 = 4122;
+const SYS_MMAP2: i64 = 4210;
+const SYS_EXIT_GROUP: i64 = 4246;
+const SYS_OPENAT: i64 = 4288;
+
+// MIPS errno values.
+const ENOENT: i64 = 2;
+const EIO: i64 = 5;
+const EBADF: i64 = 9;
+const ENOMEM: i64 = 12;
+const EACCES: i64 = 13;
+const ENODEV: i64 = 19;
+const EINVAL: i64 = 22;
+const ENOSYS: i64 = 89;
+
+// MIPS open(2) and mmap(2) flags.
+const O_ACCMODE: i64 = 0x0003;
+const O_WRONLY: i64 = 0x0001;
+const O_RDWR: i64 = 0x0002;
+const O_APPEND: i64 = 0x0008;
+const O_CREAT: i64 = 0x0100;
+const O_TRUNC: i64 = 0x0200;
+const O_EXCL: i64 = 0x0400;
+const MAP_ANONYMOUS: i64 = 0x0800;
+
+const PAGE_SIZE: u32 = 4096;
+// The most one read(2) or write(2) transfers. A larger count gets a short
+// transfer, as it may on Linux, rather than a host buffer of the size the
+// guest asked for.
+const MAX_READ: u32 = 64 * 1024;
+// Anonymous mappings are handed out upwards from here, well clear of the
+// heap below and the stack above.
+pub const MMAP_BASE: u32 = 0x2000_0000;
+
+const UTSNAME_FIELD_LENGTH: u32 = 65;
+const UTSNAME: [&str; 6] = ["Linux", "mips", "4.19.0", "#1", "mips", "(none)"];
+
+impl MipsSimulator {
+    // Arguments are in $a0-$a3 with any further ones at 16($sp) and 20($sp).
+    // The result goes in $v0 and $a3 is set to 1 when $v0 holds an errno.
+    pub fn execute_linux_syscall(&mut self) {
+        let a0 = self.registers[4];
+        let a1 = self.registers[5];
+        let a2 = self.registers[6];
+        let a3 = self.registers[7];
+        let result = match self.registers[2] {
+            SYS_EXIT | SYS_EXIT_GROUP => {
+                self.exit_code = Some((a0 & 0xFF) as i32);
+                return;
+            }
+            SYS_READ => self.linux_read(a0, a1 as u32, a2 as u32),
+            SYS_WRITE => self.linux_write(a0, a1 as u32, a2 as u32),
+            SYS_CLOSE => self.linux_close(a0),
+            // brk never fails; it reports the unchanged break instead.
+            SYS_BRK => {
+                if a0 as u32 > self.next_free_address {
+                    self.next_free_address = a0 as u32;
+                }
+                Ok(self.next_free_address as i64)
+            }
+            SYS_MMAP | SYS_MMAP2 => self.linux_mmap(a1 as u32, a3),
+            SYS_UNAME => {
+                for (i, field) in UTSNAME.iter().enumerate() {
+                    let start = (a0 as u32).wrapping_add(i as u32 * UTSNAME_FIELD_LENGTH);
+                    for offset in 0..UTSNAME_FIELD_LENGTH {
+                        let byte = *field.as_bytes().get(offset as usize).unwrap_or(&0);
+                        self.write_byte(start.wrapping_add(offset), byte);
+                    }
+                }
+                Ok(0)
+            }
+            SYS_OPENAT => self.linux_openat(a1 as u32, a2),
+            _ => Err(ENOSYS),
+        };
+        match result {
+            Ok(value) => {
+                self.registers[2] = value;
+                self.registers[7] = 0;
+            }
+            Err(errno) => {
+                self.registers[2] = errno;
+                self.registers[7] = 1;
+            }
+        }
+    }
+
+    fn linux_read(&mut self, fd: i64, address: u32, count: u32) -> Result<i64, i64> {
+        let mut buffer = vec![0; count.min(MAX_READ) as usize];
+        let read = match fd {
+            0 => self.input.read(&mut buffer),
+            _ => match self.open_files.get_mut(&fd) {
+                Some(file) => file.read(&mut buffer),
+                None => return Err(EBADF),
+            },
+        }
+        .map_err(|error| error.raw_os_error().unwrap_or(EIO as i32) as i64)?;
+        for (i, &byte) in buffer[..read].iter().enumerate() {
+            self.write_byte(address.wrapping_add(i as u32), byte);
+        }
+        Ok(read as i64)
+    }
+
+    fn linux_write(&mut self, fd: i64, address: u32, count: u32) -> Result<i64, i64> {
+        let buffer: Vec<u8> = (0..count.min(MAX_READ))
+            .map(|i| self.read_byte(address.wrapping_add(i)))
+            .collect();
+        match fd {
+            1 => self.output.write(&buffer),
+            2 => std::io::stderr().write(&buffer),
+            _ => match self.open_files.get_mut(&fd) {
+                Some(file) => file.write(&buffer),
+                None => return Err(EBADF),
+            },
+        }
+        .map(|written| written as i64)
+        .map_err(|error| error.raw_os_error().unwrap_or(EIO as i32) as i64)
+    }
+
+    fn linux_close(&mut self, fd: i64) -> Result<i64, i64> {
+        if (0..=2).contains(&fd) || self.open_files.remove(&fd).is_some() {
+            Ok(0)
+        } else {
+            Err(EBADF)
+        }
+    }
+
+    fn linux_mmap(&mut self, length: u32, flags: i64) -> Result<i64, i64> {
+        if flags & MAP_ANONYMOUS == 0 {
+            return Err(ENODEV);
+        }
+        if length == 0 {
+            return Err(EINVAL);
+        }
+        let pages = length.div_ceil(PAGE_SIZE);
+        let address = self.mmap_address;
+        let end = pages
+            .checked_mul(PAGE_SIZE)
+            .and_then(|size| address.checked_add(size))
+            .filter(|&end| end < 0x8000_0000)
+            .ok_or(ENOMEM)?;
+        // Fresh pages read as zero because they have never been written.
+        self.mmap_address = end;
+        Ok(address as i64)
+    }
+
+    // Every path, absolute or relative to any directory, is looked up inside
+    // the sandbox. Without a sandbox no file can be opened.
+    fn linux_openat(&mut self, path_address: u32, flags: i64) -> Result<i64, i64> {
+        let Some(sandbox) = &self.sandbox else {
+            return Err(EACCES);
+        };
+        let path = String::from_utf8_lossy(&self.read_c_string(path_address)).into_owned();
+        let path = sandbox_path(sandbox, &path)?;
+
+        let mut options = OpenOptions::new();
+        match flags & O_ACCMODE {
+            O_WRONLY => options.write(true),
+            O_RDWR => options.read(true).write(true),
+            _ => options.read(true),
+        };
+        options
+            .append(flags & O_APPEND != 0)
+            .truncate(flags & O_TRUNC != 0)
+            .create(flags & O_CREAT != 0)
+            .create_new(flags & O_CREAT != 0 && flags & O_EXCL != 0);
+        let file: File = options
+            .open(&path)
+            .map_err(|error| error.raw_os_error().unwrap_or(EIO as i32) as i64)?;
+
+        let fd = (3..).find(|fd| !self.open_files.contains_key(fd)).unwrap();
+        self.open_files.insert(fd, file);
+        Ok(fd)
+    }
+}
+
+// Joins a guest path onto the sandbox, refusing any path that could step
+// outside it through `..` or a symbolic link.
+fn sandbox_path(sandbox: &Path, path: &str) -> Result<PathBuf, i64> {
+    let mut joined = sandbox.to_path_buf();
+    for component in Path::new(path).components() {
+        match component {
+            Component::Normal(part) => joined.push(part),
+            Component::RootDir | Component::CurDir => {}
+            _ => return Err(EACCES),
+        }
+    }
+    let root = sandbox.canonicalize().map_err(|_| EACCES)?;
+    let resolved = match joined.canonicalize() {
+        Ok(resolved) => resolved,
+        // A file about to be created only needs its directory checked.
+        Err(_) => {
+            let (Some(parent), Some(name)) = (joined.parent(), joined.file_name()) else {
+                return Err(ENOENT);
+            };
+            parent.canonicalize().map_err(|_| ENOENT)?.join(name)
+        }
+    };
+    if resolved.starts_with(&root) {
+        Ok(resolved)
+    } else {
+        Err(EACCES)
+    }
+}
@@ -0,0 +1,182 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::ops::Range;
+
+use crate::cp0::EXCEPTION_VECTOR;
+use crate::data::split_label;
+use crate::expr::{referenced_symbols, split_instruction, split_memory_operand};
+use crate::macros::SourceLine;
+use crate::{MipsSimulator, TEXT_BASE};
+
+// Declarations name symbols without using them.
+const DECLARATIONS: [&str; 3] = [".globl", ".global", ".extern"];
+
+// One line of source as loaded, with the instructions and data it produced.
+pub struct ListingLine {
+    pub unit: usize,
+    pub source: SourceLine,
+    pub instructions: Range<usize>,
+    pub data: Range<u32>,
+}
+
+impl MipsSimulator {
+    // Address and hex columns for a line: one row per instruction word, or
+    // one per four bytes of data. Data shows bytes in memory order and skips
+    // alignment padding, which is never written.
+    fn listing_rows(&self, line: &ListingLine) -> Vec<(u32, String)> {
+        let mut rows: Vec<(u32, String)> = line
+            .instructions
+            .clone()
+            .map(|i| {
+                let word = self.binary_program.get(i).copied().unwrap_or(0);
+                (self.program[i].0, format!("{:08x}", word))
+            })
+            .collect();
+        let written: Vec<u32> = line
+            .data
+            .clone()
+            .filter(|address| self.memory.contains_key(address))
+            .collect();
+        match (written.first(), written.last()) {
+            (Some(&first), Some(&last)) => {
+                for start in (first..=last).step_by(4) {
+                    let bytes: String = (start..(start + 4).min(last + 1))
+                        .map(|address| format!("{:02x}", self.read_byte(address)))
+                        .collect();
+                    rows.push((start, bytes));
+                }
+            }
+            _ if !line.data.is_empty() => rows.push((line.data.start, String::new())),
+            _ => {}
+        }
+        rows
+    }
+
+    fn section_name(&self, address: u32) -> &'static str {
+        if address >= EXCEPTION_VECTOR {
+            ".ktext"
+        } else if address >= TEXT_BASE {
+            ".text"
+        } else {
+            ".data"
+        }
+    }
+
+    // The unit that defines a name as seen from another unit: its own label,
+    // or else a global from any unit.
+    fn defining_unit(&self, unit: usize, name: &str) -> Option<usize> {
+        if self.units[unit].labels.contains_key(name) {
+            return Some(unit);
+        }
+        self.units
+            .iter()
+            .position(|other| other.globals.contains_key(name) && other.labels.contains_key(name))
+    }
+
+    // A classic listing of every loaded file, followed by a symbol table and
+    // a cross-reference of where each label is defined and used.
+    pub fn listing(&self) -> String {
+        let mut out = String::new();
+        for (index, unit) in self.units.iter().enumerate() {
+            writeln!(out, "{}\n", unit.name).unwrap();
+            writeln!(out, " Line  Address   Code      Source").unwrap();
+            for line in self.listing.iter().filter(|line| line.unit == index) {
+                let rows = self.listing_rows(line);
+                let text = line.source.text.trim_end();
+                let Some(((address, code), rest)) = rows.split_first() else {
+                    let row = format!("{:>5}  {:20}  {}", line.source.line, "", text);
+                    writeln!(out, "{}", row.trim_end()).unwrap();
+                    continue;
+                };
+                writeln!(
+                    out,
+                    "{:>5}  {:08x}  {:8}  {}",
+                    line.source.line, address, code, text
+                )
+                .unwrap();
+                for (address, code) in rest {
+                    writeln!(out, "       {:08x}  {}", address, code).unwrap();
+                }
+            }
+            out.push('\n');
+        }
+
+        // Symbols are keyed by defining unit as well as name, since units
+        // may each have a local label of the same name.
+        let mut definitions: BTreeMap<(&str, usize), &str> = BTreeMap::new();
+        let mut uses: BTreeMap<(&str, usize), Vec<&str>> = BTreeMap::new();
+        for line in &self.listing {
+            let (label, rest) = split_label(line.source.text.trim());
+            if let Some(label) = label {
+                if self.units[line.unit].labels.contains_key(label) {
+                    definitions.insert((label, line.unit), &line.source.location);
+                }
+            }
+            let parts = split_instruction(rest);
+            if rest.is_empty() || DECLARATIONS.contains(&parts[0].as_str()) {
+                continue;
+            }
+            for operand in &parts[1..] {
+                for name in referenced_symbols(split_memory_operand(operand).0) {
+                    if let Some(unit) = self.defining_unit(line.unit, &name) {
+                        let key = self.units[unit].labels.get_key_value(&name).unwrap().0;
+                        uses.entry((key, unit))
+                            .or_default()
+                            .push(&line.source.location);
+                    }
+                }
+            }
+        }
+
+        let width = self
+            .units
+            .iter()
+            .flat_map(|unit| unit.labels.keys())
+            .map(String::len)
+            .max()
+            .unwrap_or(0)
+            .max("Name".len());
+        writeln!(out, "Symbols:\n").unwrap();
+        writeln!(out, "{:width$}  Section  Address   Scope   File", "Name").unwrap();
+        let mut symbols: Vec<(&String, usize, u32)> = self
+            .units
+            .iter()
+            .enumerate()
+            .flat_map(|(index, unit)| {
+                unit.labels
+                    .iter()
+                    .map(move |(name, &address)| (name, index, address))
+            })
+            .collect();
+        symbols.sort();
+        for &(name, unit, address) in &symbols {
+            let scope = if self.units[unit].globals.contains_key(name) {
+                "global"
+            } else {
+                "local"
+            };
+            writeln!(
+                out,
+                "{:width$}  {:7}  {:08x}  {:6}  {}",
+                name,
+                self.section_name(address),
+                address,
+                scope,
+                self.units[unit].name
+            )
+            .unwrap();
+        }
+
+        writeln!(out, "\nCross-reference:\n").unwrap();
+        for &(name, unit, _) in &symbols {
+            let key = (name.as_str(), unit);
+            let defined = definitions.get(&key).copied().unwrap_or("?");
+            let used = match uses.get(&key) {
+                Some(uses) => format!("used at {}", uses.join(", ")),
+                None => "never used".to_string(),
+            };
+            writeln!(out, "{:width$}  defined at {}; {}", name, defined, used).unwrap();
+        }
+        out
+    }
+}
@@ -0,0 +1,179 @@
+use std::collections::HashMap;
+
+use crate::data::split_label;
+use crate::expr::split_operands;
+use crate::MipsSimulator;
+
+// Deeper nesting than this is taken to be a macro that invokes itself.
+const MAX_EXPANSION_DEPTH: usize = 64;
+
+// One line of assembly together with where it came from, for diagnostics
+// and listings.
+#[derive(Clone, Debug, Default)]
+pub struct SourceLine {
+    pub text: String,
+    pub line: usize,
+    pub location: String,
+}
+
+pub struct Macro {
+    parameters: Vec<String>,
+    body: Vec<SourceLine>,
+}
+
+fn is_identifier_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_' || c == '.'
+}
+
+// Replaces whole identifiers only, leaving string and character literals
+// alone, so renaming `loop` does not touch `loop2` or "loop". A leading % is
+// part of the identifier, so parameter %h does not touch %hi.
+fn replace_identifiers(text: &str, replacement: impl Fn(&str) -> Option<String>) -> String {
+    let mut result = String::new();
+    let mut rest = text;
+    while let Some(c) = rest.chars().next() {
+        if c == '"' || c == '\'' {
+            let end = rest[1..].find(c).map_or(rest.len(), |end| end + 2);
+            result.push_str(&rest[..end]);
+            rest = &rest[end..];
+        } else if c == '%' || is_identifier_char(c) {
+            let end = rest[1..]
+                .find(|c| !is_identifier_char(c))
+                .map_or(rest.len(), |end| end + 1);
+            let word = &rest[..end];
+            result.push_str(&replacement(word).unwrap_or_else(|| word.to_string()));
+            rest = &rest[end..];
+        } else {
+            result.push(c);
+            rest = &rest[c.len_utf8()..];
+        }
+    }
+    result
+}
+
+// Splits `name(a, b)` or `name a, b` into the name and its arguments.
+fn split_invocation(text: &str) -> (&str, Vec<String>) {
+    let end = text
+        .find(|c: char| c == '(' || c.is_whitespace())
+        .unwrap_or(text.len());
+    let (name, rest) = text.split_at(end);
+    let rest = rest.trim();
+    let arguments = match rest.strip_prefix('(').and_then(|r| r.strip_suffix(')')) {
+        Some(inner) => split_operands(inner),
+        None => split_operands(rest),
+    };
+    (name, arguments)
+}
+
+impl MipsSimulator {
+    // Collects `.macro name(%a, %b)` ... `.end_macro` definitions and replaces
+    // every invocation with the macro body. Macros may be invoked from other
+    // macros, and are told apart by name and argument count.
+    pub fn expand_macros(&mut self, lines: Vec<SourceLine>) -> Vec<SourceLine> {
+        let mut expanded = vec![];
+        let mut lines = lines.into_iter();
+        while let Some(source) = lines.next() {
+            let text = source.text.trim();
+            if let Some(header) = text.strip_prefix(".macro") {
+                let (name, parameters) = split_invocation(header.trim());
+                let mut body = vec![];
+                loop {
+                    match lines.next() {
+                        Some(line) if line.text.trim().starts_with(".end_macro") => break,
+                        Some(line) if line.text.trim().starts_with(".macro") => {
+                            self.report_error_at(
+                                "Macro definitions cannot be nested",
+                                &line.location,
+                            );
+                        }
+                        Some(line) => body.push(line),
+                        None => {
+                            self.report_error_at(
+                                &format!("Macro {} is missing .end_macro", name),
+                                &source.location,
+                            );
+                            break;
+                        }
+                    }
+                }
+                self.macros.insert(
+                    (name.to_string(), parameters.len()),
+                    Macro { parameters, body },
+                );
+                continue;
+            }
+            self.expand_line(source, 0, &mut expanded);
+        }
+        expanded
+    }
+
+    fn expand_line(&mut self, source: SourceLine, depth: usize, expanded: &mut Vec<SourceLine>) {
+        let (label, rest) = split_label(source.text.trim());
+        let (name, arguments) = split_invocation(rest);
+        let key = (name.to_string(), arguments.len());
+        if rest.is_empty() || !self.macros.contains_key(&key) {
+            expanded.push(source);
+            return;
+        }
+        if depth == MAX_EXPANSION_DEPTH {
+            self.report_error_at(
+                &format!("Macro {} expands too deeply; is it recursive?", name),
+                &source.location,
+            );
+            return;
+        }
+        if let Some(label) = label {
+            expanded.push(SourceLine {
+                text: format!("{}:", label),
+                ..source.clone()
+            });
+        }
+
+        // Labels defined inside the body get a suffix unique to this
+        // expansion, so the macro can be used more than once.
+        self.macro_expansions += 1;
+        let suffix = format!("_M{}", self.macro_expansions);
+        let definition = &self.macros[&key];
+        let local_labels: Vec<String> = definition
+            .body
+            .iter()
+            .filter_map(|line| split_label(line.text.trim()).0.map(str::to_string))
+            .collect();
+        let parameters: HashMap<&str, &String> = definition
+            .parameters
+            .iter()
+            .map(String::as_str)
+            .zip(&arguments)
+            .collect();
+
+        let body: Vec<SourceLine> = definition
+            .body
+            .iter()
+            .map(|line| {
+                let text = replace_identifiers(&line.text, |word| {
+                    parameters.get(word).map(|argument| argument.to_string())
+                });
+                let text = replace_identifiers(&text, |word| {
+                    local_labels
+                        .iter()
+                        .any(|label| label == word)
+                        .then(|| format!("{}{}", word, suffix))
+                });
+                SourceLine {
+                    text,
+                    line: line.line,
+                    location: format!(
+                        "{} in macro {} called from {}",
+                        line.location, name, source.location
+                    ),
+                }
+            })
+            .collect();
+        for line in body {
+            self.expand_line(line, depth + 1, expanded);
+        }
+    }
+}
+
+// Macros are keyed by name and argument count.
+pub type MacroTable = HashMap<(String, usize), Macro>;
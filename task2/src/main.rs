@@ -1,25 +1,120 @@
-use std::collections::HashMap;
+use std::collections::{BTreeSet, HashMap};
 use std::fs::File;
-use std::io::{BufRead, BufReader};
+use std::io::{BufRead, BufReader, Write};
+use std::ops::{Index, IndexMut};
+use std::path::PathBuf;
+
+mod cp0;
+mod data;
+mod elf;
+mod export;
+mod expr;
+mod fpu;
+mod link;
+mod linux;
+mod listing;
+mod macros;
+mod mips64;
+mod pseudo;
+mod syscall;
+
+use cp0::{Exception, EXCEPTION_VECTOR, STATUS, STATUS_RESET};
+use data::split_label;
+use elf::{is_elf_file, Relocation};
+use export::{DumpFormat, Segment};
+use expr::{referenced_symbols, split_instruction};
+use fpu::is_fpu_instruction;
+use link::ObjectUnit;
+use linux::MMAP_BASE;
+use listing::ListingLine;
+use macros::{MacroTable, SourceLine};
+use mips64::is_mips64_instruction;
+use syscall::SyscallAbi;
+
+const TEXT_BASE: u32 = 0x0040_0000;
+const DATA_BASE: u32 = 0x0000_1000;
+const GLOBAL_POINTER: i64 = 0x1000_8000;
+const STACK_POINTER: i64 = 0x7FFF_EFFC;
 
 struct DecodedInstruction {
     opcode: u8,
     rs: u8,
     rt: u8,
     rd: u8,
+    shamt: u8,
     funct: u8,
     immediate: u16,
     address: u32,
 }
 
+// The general-purpose registers. Every write is noted, so that a MIPS I load
+// can tell whether the instruction in its delay slot wrote the same register,
+// even with the value it already held.
+struct Registers {
+    values: [i64; 32],
+    written: u32,
+}
+
+impl Index<usize> for Registers {
+    type Output = i64;
+
+    fn index(&self, index: usize) -> &i64 {
+        &self.values[index]
+    }
+}
+
+impl IndexMut<usize> for Registers {
+    fn index_mut(&mut self, index: usize) -> &mut i64 {
+        self.written |= 1 << index;
+        &mut self.values[index]
+    }
+}
+
 struct MipsSimulator {
-    registers: [i32; 32],
-    memory: HashMap<u32, i32>,
+    registers: Registers,
+    hi: i64,
+    lo: i64,
+    fpr: [u32; 32],
+    fcsr: u32,
+    cp0: [u32; 32],
+    exception_raised: bool,
+    memory: HashMap<u32, u8>,
+    big_endian: bool,
+    mips64: bool,
+    delay_slots: bool,
+    load_delay_slots: bool,
+    objects: bool,
+    pending_branch: Option<u32>,
+    delay_slot_target: Option<u32>,
+    pending_load: Option<(usize, i64)>,
+    delayed_load: Option<(usize, i64)>,
+    ll_bit: bool,
+    ll_address: u32,
     labels: HashMap<String, u32>,
+    constants: HashMap<String, i64>,
+    data_fixups: Vec<(u32, u32, String, String)>,
+    macros: MacroTable,
+    macro_expansions: usize,
+    // Where the line being loaded or assembled came from, for error messages.
+    source_location: String,
+    program_sources: Vec<SourceLine>,
+    units: Vec<ObjectUnit>,
+    // Names the unit being assembled leaves for a later link.
+    undefined_symbols: BTreeSet<String>,
+    relocations: Vec<Relocation>,
+    listing: Vec<ListingLine>,
     pc: u32,
-    program: Vec<String>,
+    program: Vec<(u32, String)>,
     binary_program: Vec<u32>,
+    instruction_index: HashMap<u32, usize>,
     next_free_address: u32,
+    input: Box<dyn BufRead>,
+    output: Box<dyn Write>,
+    exit_code: Option<i32>,
+    abi: SyscallAbi,
+    sandbox: Option<PathBuf>,
+    open_files: HashMap<i64, File>,
+    mmap_address: u32,
     reg_map: HashMap<String, usize>,
     reg_map_rev: HashMap<usize, String>,
 }
@@ -63,67 +158,182 @@ impl MipsSimulator {
 
         let reg_map_rev = reg_map.iter().map(|(k, &v)| (v, k.clone())).collect();
 
+        let mut registers = [0; 32];
+        registers[28] = GLOBAL_POINTER;
+        registers[29] = STACK_POINTER;
+
+        let mut cp0 = [0; 32];
+        cp0[STATUS] = STATUS_RESET;
+
         MipsSimulator {
-            registers: [0; 32],
+            registers: Registers {
+                values: registers,
+                written: 0,
+            },
+            hi: 0,
+            lo: 0,
+            fpr: [0; 32],
+            fcsr: 0,
+            cp0,
+            exception_raised: false,
             memory: HashMap::new(),
+            big_endian: false,
+            mips64: false,
+            delay_slots: false,
+            load_delay_slots: false,
+            objects: false,
+            pending_branch: None,
+            delay_slot_target: None,
+            pending_load: None,
+            delayed_load: None,
+            ll_bit: false,
+            ll_address: 0,
             labels: HashMap::new(),
-            pc: 0,
+            constants: HashMap::new(),
+            data_fixups: vec![],
+            macros: HashMap::new(),
+            macro_expansions: 0,
+            source_location: String::new(),
+            program_sources: vec![],
+            units: vec![],
+            undefined_symbols: BTreeSet::new(),
+            relocations: vec![],
+            listing: vec![],
+            pc: TEXT_BASE,
             program: vec![],
             binary_program: vec![],
-            next_free_address: 0x1000,
+            instruction_index: HashMap::new(),
+            next_free_address: DATA_BASE,
+            input: Box::new(BufReader::new(std::io::stdin())),
+            output: Box::new(std::io::stdout()),
+            exit_code: None,
+            abi: SyscallAbi::Mars,
+            sandbox: None,
+            open_files: HashMap::new(),
+            mmap_address: MMAP_BASE,
             reg_map,
             reg_map_rev,
         }
     }
 
+    // Assembles one file as a unit of its own. Labels, constants and macros
+    // from files loaded earlier are not visible to it until linking.
     fn load_program_from_file(&mut self, filename: &str) {
-        let file = File::open(filename).expect("Could not open file");
-        let reader = BufReader::new(file);
-        let mut current_address = 0;
-
-        for line in reader.lines() {
-            if let Ok(instruction) = line {
-                let instruction = instruction.trim();
-                if instruction.starts_with(".data") || instruction.starts_with(".text") {
-                    continue;
+        let contents = std::fs::read_to_string(filename).expect("Could not open file");
+        let lines = self.read_source(filename, &contents, 0);
+        self.labels.clear();
+        self.constants.clear();
+        self.macros.clear();
+        self.align_data(8);
+        let mut unit = ObjectUnit {
+            name: filename.to_string(),
+            program: self.program.len()..self.program.len(),
+            data: self.next_free_address..self.next_free_address,
+            ..Default::default()
+        };
+        let lines = self.expand_macros(lines);
+        let mut current_address = self.next_text_address(false);
+        // Whichever of .text/.ktext is not current keeps its next address here.
+        let mut other_text_address = self.next_text_address(true);
+        let mut in_kernel_text = false;
+        let mut in_data = false;
+        // Data labels wait for the next directive so they land after its
+        // alignment padding.
+        let mut data_labels = vec![];
+
+        for source in lines {
+            self.source_location = source.location.clone();
+            self.listing.push(ListingLine {
+                unit: self.units.len(),
+                source: source.clone(),
+                instructions: self.program.len()..self.program.len(),
+                data: self.next_free_address..self.next_free_address,
+            });
+            let instruction = source.text.trim();
+            if instruction.starts_with(".text") || instruction.starts_with(".ktext") {
+                let kernel = instruction.starts_with(".ktext");
+                if kernel != in_kernel_text {
+                    std::mem::swap(&mut current_address, &mut other_text_address);
+                    in_kernel_text = kernel;
                 }
+                self.bind_data_labels(&mut data_labels);
+                in_data = false;
+                continue;
+            }
+            if instruction.starts_with(".data") {
+                in_data = true;
+                continue;
+            }
 
-                if instruction.contains(":") {
-                    let label_and_rest: Vec<&str> = instruction.splitn(2, ':').collect();
-                    let label = label_and_rest[0].trim().to_string();
-                    let rest = label_and_rest.get(1).unwrap_or(&"").trim();
-
-                    if rest.starts_with(".word") {
-                        let parts: Vec<&str> = rest.split_whitespace().collect();
-                        if parts.len() >= 2 {
-                            let value: i32 = parts[1].parse().unwrap_or(0);
-                            self.memory.insert(self.next_free_address, value);
-                            self.labels.insert(label, self.next_free_address);
-                            self.next_free_address += 4;
-                        }
-                    } else {
-                        self.labels.insert(label, current_address);
-                        if !rest.is_empty() {
-                            self.program.push(rest.to_string());
-                            current_address += 1;
-                        }
-                    }
-                    continue;
+            let (label, rest) = split_label(instruction);
+            if let Some(label) = label {
+                if in_data || rest.starts_with('.') {
+                    data_labels.push(label.to_string());
+                } else {
+                    self.labels.insert(label.to_string(), current_address);
                 }
+            }
 
-                if !instruction.is_empty() {
-                    self.program.push(instruction.to_string());
-                    current_address += 1;
+            if unit.declare_symbols(rest, &source.location) {
+                continue;
+            }
+            if rest.starts_with('.') {
+                self.assemble_data_directive(rest, &mut data_labels);
+            } else if !rest.is_empty() {
+                for expanded in self.expand_pseudo_instruction(rest) {
+                    self.program.push((current_address, expanded));
+                    self.program_sources.push(source.clone());
+                    current_address += 4;
                 }
             }
+            let line = self.listing.last_mut().unwrap();
+            line.instructions.end = self.program.len();
+            line.data.end = self.next_free_address;
         }
+        self.bind_data_labels(&mut data_labels);
+        unit.labels = std::mem::take(&mut self.labels);
+        unit.constants = std::mem::take(&mut self.constants);
+        unit.data_fixups = std::mem::take(&mut self.data_fixups);
+        unit.program.end = self.program.len();
+        unit.data.end = self.next_free_address;
+        self.units.push(unit);
+    }
+
+    // Where the next file's user or kernel text starts.
+    fn next_text_address(&self, kernel: bool) -> u32 {
+        let base = if kernel { EXCEPTION_VECTOR } else { TEXT_BASE };
+        self.program
+            .iter()
+            .map(|&(address, _)| address)
+            .filter(|&address| (address >= EXCEPTION_VECTOR) == kernel)
+            .max()
+            .map_or(base, |address| address + 4)
     }
 
+    // Loaded files are linked before they are assembled. A program placed
+    // directly in `program` is assembled against the current labels, and one
+    // loaded from an executable needs no assembling.
     fn assemble_program(&mut self) {
-        for (i, instruction) in self.program.iter().enumerate() {
+        if self.units.is_empty() {
+            self.assemble_instructions(self.binary_program.len()..self.program.len());
+        } else {
+            self.link();
+        }
+        self.source_location.clear();
+    }
+
+    fn assemble_instructions(&mut self, range: std::ops::Range<usize>) {
+        for i in range {
+            let (address, instruction) = &self.program[i];
+            if let Some(source) = self.program_sources.get(i) {
+                self.source_location = source.location.clone();
+            }
             let (opcode, parts) = self.instruction_decode_assembly(instruction.clone());
+            let address = *address;
             let binary_instruction =
-                self.assemble_instruction(opcode.clone(), parts.clone(), i as u32);
+                self.assemble_instruction(opcode.clone(), parts.clone(), address);
+            self.instruction_index.insert(address, i);
+            self.record_relocation(address, binary_instruction, &parts);
 
             self.print_binary_instruction(&parts, binary_instruction);
 
@@ -132,10 +342,7 @@ impl MipsSimulator {
     }
 
     fn instruction_decode_assembly(&self, instruction: String) -> (String, Vec<String>) {
-        let parts: Vec<String> = instruction
-            .split_whitespace()
-            .map(|p| p.trim_end_matches(',').to_string())
-            .collect();
+        let parts = split_instruction(&instruction);
         let opcode = parts[0].clone();
         (opcode, parts)
     }
@@ -147,94 +354,331 @@ impl MipsSimulator {
         current_address: u32,
     ) -> u32 {
         match opcode.as_str() {
-            "add" | "sub" | "and" | "or" | "slt" => {
+            "add" | "addu" | "sub" | "subu" | "and" | "or" | "xor" | "nor" | "slt" | "sltu"
+            | "movz" | "movn" => {
                 let funct_map = HashMap::from([
+                    ("movz", 10),
+                    ("movn", 11),
                     ("add", 32),
+                    ("addu", 33),
                     ("sub", 34),
+                    ("subu", 35),
                     ("and", 36),
                     ("or", 37),
+                    ("xor", 38),
+                    ("nor", 39),
                     ("slt", 42),
+                    ("sltu", 43),
                 ]);
                 let rs = self.get_register_index(&parts[2]) as u32;
                 let rt = self.get_register_index(&parts[3]) as u32;
                 let rd = self.get_register_index(&parts[1]) as u32;
                 let shamt = 0;
                 let funct = *funct_map.get(opcode.as_str()).unwrap();
-                let binary = (0 << 26)
-                    | (rs << 21)
-                    | (rt << 16)
-                    | (rd << 11)
-                    | (shamt << 6)
-                    | funct;
-                binary
-            }
-            "addi" => {
-                let opcode_num = 8;
+                (rs << 21) | (rt << 16) | (rd << 11) | (shamt << 6) | funct
+            }
+            "sll" | "srl" | "sra" => {
+                let funct_map = HashMap::from([("sll", 0), ("srl", 2), ("sra", 3)]);
+                let rt = self.get_register_index(&parts[2]) as u32;
+                let rd = self.get_register_index(&parts[1]) as u32;
+                let shamt = self.unsigned_field(&parts[3], 5, "Shift amount");
+                let funct = *funct_map.get(opcode.as_str()).unwrap();
+                (rt << 16) | (rd << 11) | (shamt << 6) | funct
+            }
+            "rotr" => {
+                // rotr reuses the srl encoding with the R bit (bit 21) set.
+                let rt = self.get_register_index(&parts[2]) as u32;
+                let rd = self.get_register_index(&parts[1]) as u32;
+                let shamt = self.unsigned_field(&parts[3], 5, "Rotate amount");
+                (1 << 21) | (rt << 16) | (rd << 11) | (shamt << 6) | 2
+            }
+            "rotrv" => {
+                // rotrv reuses the srlv encoding with the R bit (bit 6) set.
+                let rs = self.get_register_index(&parts[3]) as u32;
+                let rt = self.get_register_index(&parts[2]) as u32;
+                let rd = self.get_register_index(&parts[1]) as u32;
+                (rs << 21) | (rt << 16) | (rd << 11) | (1 << 6) | 6
+            }
+            "ext" | "ins" => {
+                // ext stores size - 1 in the rd field, ins stores pos + size - 1.
+                let rt = self.get_register_index(&parts[1]) as u32;
+                let rs = self.get_register_index(&parts[2]) as u32;
+                let pos = self.immediate_operand(&parts[3]);
+                let size = self.immediate_operand(&parts[4]);
+                if pos < 0 || size < 1 || pos + size > 32 {
+                    self.report_error(&format!(
+                        "Bit field at position {} of size {} does not fit in a word",
+                        pos, size
+                    ));
+                    return 0;
+                }
+                let (pos, size) = (pos as u32, size as u32);
+                let (msb, funct) = if opcode == "ext" {
+                    (size - 1, 0)
+                } else {
+                    (pos + size - 1, 4)
+                };
+                (31 << 26) | (rs << 21) | (rt << 16) | (msb << 11) | (pos << 6) | funct
+            }
+            "wsbh" | "seb" | "seh" => {
+                let op_map = HashMap::from([("wsbh", 2), ("seb", 16), ("seh", 24)]);
+                let rt = self.get_register_index(&parts[2]) as u32;
+                let rd = self.get_register_index(&parts[1]) as u32;
+                let op = *op_map.get(opcode.as_str()).unwrap();
+                (31 << 26) | (rt << 16) | (rd << 11) | (op << 6) | 32
+            }
+            "sllv" | "srlv" | "srav" => {
+                let funct_map = HashMap::from([("sllv", 4), ("srlv", 6), ("srav", 7)]);
+                let rs = self.get_register_index(&parts[3]) as u32;
+                let rt = self.get_register_index(&parts[2]) as u32;
+                let rd = self.get_register_index(&parts[1]) as u32;
+                let funct = *funct_map.get(opcode.as_str()).unwrap();
+                (rs << 21) | (rt << 16) | (rd << 11) | funct
+            }
+            "mult" | "multu" | "div" | "divu" => {
+                let funct_map =
+                    HashMap::from([("mult", 24), ("multu", 25), ("div", 26), ("divu", 27)]);
+                let rs = self.get_register_index(&parts[1]) as u32;
+                let rt = self.get_register_index(&parts[2]) as u32;
+                let funct = *funct_map.get(opcode.as_str()).unwrap();
+                (rs << 21) | (rt << 16) | funct
+            }
+            "mul" => {
+                let rs = self.get_register_index(&parts[2]) as u32;
+                let rt = self.get_register_index(&parts[3]) as u32;
+                let rd = self.get_register_index(&parts[1]) as u32;
+                (28 << 26) | (rs << 21) | (rt << 16) | (rd << 11) | 2
+            }
+            "madd" | "maddu" | "msub" | "msubu" => {
+                let funct_map =
+                    HashMap::from([("madd", 0), ("maddu", 1), ("msub", 4), ("msubu", 5)]);
+                let rs = self.get_register_index(&parts[1]) as u32;
+                let rt = self.get_register_index(&parts[2]) as u32;
+                let funct = *funct_map.get(opcode.as_str()).unwrap();
+                (28 << 26) | (rs << 21) | (rt << 16) | funct
+            }
+            "clz" | "clo" => {
+                // The architecture requires rt to repeat rd in the encoding.
+                let funct = if opcode == "clz" { 32 } else { 33 };
+                let rs = self.get_register_index(&parts[2]) as u32;
+                let rd = self.get_register_index(&parts[1]) as u32;
+                (28 << 26) | (rs << 21) | (rd << 16) | (rd << 11) | funct
+            }
+            "mfhi" | "mflo" => {
+                let funct = if opcode == "mfhi" { 16 } else { 18 };
+                let rd = self.get_register_index(&parts[1]) as u32;
+                (rd << 11) | funct
+            }
+            "mthi" | "mtlo" => {
+                let funct = if opcode == "mthi" { 17 } else { 19 };
+                let rs = self.get_register_index(&parts[1]) as u32;
+                (rs << 21) | funct
+            }
+            "jr" => {
+                let rs = self.get_register_index(&parts[1]) as u32;
+                (rs << 21) | 8
+            }
+            "jalr" => {
+                // `jalr rs` links through $ra; `jalr rd, rs` names the link register.
+                let (rd, rs) = if parts.len() > 2 {
+                    (
+                        self.get_register_index(&parts[1]),
+                        self.get_register_index(&parts[2]),
+                    )
+                } else {
+                    (31, self.get_register_index(&parts[1]))
+                };
+                ((rs as u32) << 21) | ((rd as u32) << 11) | 9
+            }
+            "addi" | "addiu" | "slti" | "sltiu" => {
+                let opcode_map =
+                    HashMap::from([("addi", 8), ("addiu", 9), ("slti", 10), ("sltiu", 11)]);
+                let opcode_num = *opcode_map.get(opcode.as_str()).unwrap();
                 let rs = self.get_register_index(&parts[2]) as u32;
                 let rt = self.get_register_index(&parts[1]) as u32;
-                let immediate = parts[3].parse::<i16>().unwrap_or(0) as u32 & 0xFFFF;
-                let binary = (opcode_num << 26) | (rs << 21) | (rt << 16) | immediate;
-                binary
+                let immediate = self.immediate_field(&parts[3], true);
+                (opcode_num << 26) | (rs << 21) | (rt << 16) | immediate
             }
-            "lw" | "sw" => {
-                let opcode_num = if opcode == "lw" { 35 } else { 43 };
+            "andi" | "ori" | "xori" => {
+                let opcode_map = HashMap::from([("andi", 12), ("ori", 13), ("xori", 14)]);
+                let opcode_num = *opcode_map.get(opcode.as_str()).unwrap();
+                let rs = self.get_register_index(&parts[2]) as u32;
                 let rt = self.get_register_index(&parts[1]) as u32;
-                let address_part = parts[2].clone();
-
-                if let Some(&label_address) = self.labels.get(&address_part) {
-                    let rs = 0;
-                    let immediate = (label_address as i32) as u16 as u32;
-                    let binary = (opcode_num << 26) | (rs << 21) | (rt << 16) | immediate;
-                    binary
-                } else if address_part.contains("(") && address_part.contains(")") {
-                    let offset_base: Vec<&str> = address_part.split('(').collect();
-                    let offset = offset_base[0].parse::<i16>().unwrap_or(0) as u32 & 0xFFFF;
-                    let base_reg = offset_base[1].trim_end_matches(')');
-                    let rs = self.get_register_index(base_reg) as u32;
-                    let binary = (opcode_num << 26) | (rs << 21) | (rt << 16) | offset;
-                    binary
-                } else {
-                    println!("Error: Label {} not found", address_part);
-                    0
-                }
+                // Logical immediates are zero-extended, so accept the full 0..=65535 range.
+                let immediate = self.immediate_field(&parts[3], false);
+                (opcode_num << 26) | (rs << 21) | (rt << 16) | immediate
             }
-            "beq" => {
-                let opcode_num = 4;
+            "lui" => {
+                let opcode_num = 15;
+                let rt = self.get_register_index(&parts[1]) as u32;
+                let immediate = self.immediate_field(&parts[2], false);
+                (opcode_num << 26) | (rt << 16) | immediate
+            }
+            "lb" | "lh" | "lwl" | "lw" | "lbu" | "lhu" | "lwr" | "sb" | "sh" | "swl" | "sw"
+            | "swr" | "ll" | "sc" => {
+                let opcode_map = HashMap::from([
+                    ("lb", 32),
+                    ("lh", 33),
+                    ("lwl", 34),
+                    ("lw", 35),
+                    ("lbu", 36),
+                    ("lhu", 37),
+                    ("lwr", 38),
+                    ("sb", 40),
+                    ("sh", 41),
+                    ("swl", 42),
+                    ("sw", 43),
+                    ("swr", 46),
+                    ("ll", 48),
+                    ("sc", 56),
+                ]);
+                let opcode_num = *opcode_map.get(opcode.as_str()).unwrap();
+                let rt = self.get_register_index(&parts[1]) as u32;
+                // Without a base register the address is absolute, relative to $zero.
+                let (offset, base) = self.memory_operand(&parts[2]);
+                let rs = base.unwrap_or(0) as u32;
+                (opcode_num << 26) | (rs << 21) | (rt << 16) | offset
+            }
+            "beq" | "bne" => {
+                let opcode_num = if opcode == "beq" { 4 } else { 5 };
                 let rs = self.get_register_index(&parts[1]) as u32;
                 let rt = self.get_register_index(&parts[2]) as u32;
                 let label = &parts[3];
-                if let Some(&label_address) = self.labels.get(label) {
-                    let offset =
-                        ((label_address as i32 - (current_address as i32 + 1)) as i16) as u16
-                            as u32;
-                    let binary = (opcode_num << 26) | (rs << 21) | (rt << 16) | offset;
-                    binary
-                } else {
-                    println!("Error: Label {} not found", label);
-                    0
+                match self.branch_offset(label, current_address) {
+                    Ok(offset) => (opcode_num << 26) | (rs << 21) | (rt << 16) | offset,
+                    Err(message) => {
+                        self.report_error(&message);
+                        0
+                    }
                 }
             }
-            "j" => {
-                let opcode_num = 2;
+            "blez" | "bgtz" | "bltz" | "bgez" | "bltzal" | "bgezal" => {
+                // blez/bgtz have their own opcodes; the rest share REGIMM (opcode 1)
+                // and are told apart by the rt field.
+                let encoding_map = HashMap::from([
+                    ("blez", (6, 0)),
+                    ("bgtz", (7, 0)),
+                    ("bltz", (1, 0)),
+                    ("bgez", (1, 1)),
+                    ("bltzal", (1, 16)),
+                    ("bgezal", (1, 17)),
+                ]);
+                let (opcode_num, rt) = *encoding_map.get(opcode.as_str()).unwrap();
+                let rs = self.get_register_index(&parts[1]) as u32;
+                let label = &parts[2];
+                match self.branch_offset(label, current_address) {
+                    Ok(offset) => (opcode_num << 26) | (rs << 21) | (rt << 16) | offset,
+                    Err(message) => {
+                        self.report_error(&message);
+                        0
+                    }
+                }
+            }
+            "j" | "jal" => {
+                let opcode_num = if opcode == "j" { 2 } else { 3 };
                 let label = &parts[1];
-                if let Some(&label_address) = self.labels.get(label) {
-                    let address = label_address & 0x3FFFFFF;
-                    let binary = (opcode_num << 26) | address;
-                    binary
+                if let Ok(label_address) = self.try_evaluate(label) {
+                    let address = (label_address as u32 >> 2) & 0x3FFFFFF;
+                    (opcode_num << 26) | address
                 } else {
-                    println!("Error: Label {} not found", label);
+                    self.report_error(&format!("Label {} not found", label));
                     0
                 }
             }
-            _ => 0,
+            "tge" | "tgeu" | "tlt" | "tltu" | "teq" | "tne" => {
+                let funct_map = HashMap::from([
+                    ("tge", 48),
+                    ("tgeu", 49),
+                    ("tlt", 50),
+                    ("tltu", 51),
+                    ("teq", 52),
+                    ("tne", 54),
+                ]);
+                let rs = self.get_register_index(&parts[1]) as u32;
+                let rt = self.get_register_index(&parts[2]) as u32;
+                let code = parts
+                    .get(3)
+                    .map_or(0, |c| self.unsigned_field(c, 10, "Trap code"));
+                let funct = *funct_map.get(opcode.as_str()).unwrap();
+                (rs << 21) | (rt << 16) | (code << 6) | funct
+            }
+            "tgei" | "tgeiu" | "tlti" | "tltiu" | "teqi" | "tnei" => {
+                let rt_map = HashMap::from([
+                    ("tgei", 8),
+                    ("tgeiu", 9),
+                    ("tlti", 10),
+                    ("tltiu", 11),
+                    ("teqi", 12),
+                    ("tnei", 14),
+                ]);
+                let rs = self.get_register_index(&parts[1]) as u32;
+                let rt = *rt_map.get(opcode.as_str()).unwrap();
+                let immediate = self.immediate_field(&parts[2], true);
+                (1 << 26) | (rs << 21) | (rt << 16) | immediate
+            }
+            "syscall" => 12,
+            "break" => {
+                let code = parts
+                    .get(1)
+                    .map_or(0, |c| self.unsigned_field(c, 20, "Break code"));
+                (code << 6) | 13
+            }
+            "mfc0" | "mtc0" => {
+                let rs = if opcode == "mfc0" { 0 } else { 4 };
+                let rt = self.get_register_index(&parts[1]) as u32;
+                let rd = self.get_cp0_register_index(&parts[2]) as u32;
+                (16 << 26) | (rs << 21) | (rt << 16) | (rd << 11)
+            }
+            "eret" => (16 << 26) | (16 << 21) | 24,
+            op if is_fpu_instruction(op) => {
+                self.assemble_fpu_instruction(op, &parts, current_address)
+            }
+            op if is_mips64_instruction(op) => self.assemble_mips64_instruction(op, &parts),
+            _ => {
+                self.report_error(&format!("Unknown instruction {}", opcode));
+                0
+            }
+        }
+    }
+
+    // The target may be any expression, such as `loop` or `table+8`. It must
+    // be a word address within 32K instructions of the delay slot.
+    fn branch_offset(&self, label: &str, current_address: u32) -> Result<u32, String> {
+        // A branch to an undefined name is filled in by its relocation.
+        if referenced_symbols(label)
+            .iter()
+            .any(|name| self.undefined_symbols.contains(name))
+        {
+            return Ok(0);
+        }
+        let target = self
+            .try_evaluate(label)
+            .map_err(|_| format!("Label {} not found", label))?;
+        if target % 4 != 0 {
+            return Err(format!("Branch target {} is not word aligned", label));
+        }
+        let offset = (target - (current_address as i64 + 4)) >> 2;
+        match i16::try_from(offset) {
+            Ok(offset) => Ok(offset as u16 as u32),
+            Err(_) => Err(format!(
+                "Branch target {} is out of range ({} instructions away)",
+                label, offset
+            )),
         }
     }
 
-    fn print_binary_instruction(&self, parts: &Vec<String>, binary_instruction: u32) {
+    fn print_binary_instruction(&self, parts: &[String], binary_instruction: u32) {
         let opcode = parts[0].clone();
         let bin_str = format!("{:032b}", binary_instruction);
         let formatted_bin = match opcode.as_str() {
-            "add" | "sub" | "and" | "or" | "slt" => {
+            "add" | "addu" | "sub" | "subu" | "and" | "or" | "xor" | "nor" | "slt" | "sltu"
+            | "movz" | "movn" | "sll" | "srl" | "sra" | "sllv" | "srlv" | "srav" | "mult"
+            | "multu" | "div" | "divu" | "mfhi" | "mflo" | "mthi" | "mtlo" | "jr" | "jalr"
+            | "mul" | "madd" | "maddu" | "msub" | "msubu" | "clz" | "clo" | "syscall" | "break"
+            | "mfc0" | "mtc0" | "eret" | "tge" | "tgeu" | "tlt" | "tltu" | "teq" | "tne"
+            | "rotr" | "rotrv" | "ext" | "ins" | "wsbh" | "seb" | "seh" | "daddu" | "dsubu"
+            | "dsll" | "dsrl" | "dsra" | "dsll32" | "dsrl32" | "dsra32" | "dmult" | "dmultu"
+            | "ddiv" | "ddivu" => {
                 format!(
                     "{} {} {} {} {} {}",
                     &bin_str[0..6],
@@ -245,7 +689,11 @@ impl MipsSimulator {
                     &bin_str[26..32]
                 )
             }
-            "addi" | "lw" | "sw" | "beq" => {
+            "addi" | "addiu" | "slti" | "sltiu" | "andi" | "ori" | "xori" | "lui" | "lb" | "lh"
+            | "lwl" | "lw" | "lbu" | "lhu" | "lwr" | "sb" | "sh" | "swl" | "sw" | "swr" | "ll"
+            | "sc" | "daddiu" | "ld" | "sd" | "lwu" | "beq" | "bne" | "blez" | "bgtz" | "bltz"
+            | "bgez" | "bltzal" | "bgezal" | "tgei" | "tgeiu" | "tlti" | "tltiu" | "teqi"
+            | "tnei" => {
                 format!(
                     "{} {} {} {}",
                     &bin_str[0..6],
@@ -254,7 +702,27 @@ impl MipsSimulator {
                     &bin_str[16..32]
                 )
             }
-            "j" => {
+            "lwc1" | "swc1" | "ldc1" | "sdc1" | "bc1t" | "bc1f" => {
+                format!(
+                    "{} {} {} {}",
+                    &bin_str[0..6],
+                    &bin_str[6..11],
+                    &bin_str[11..16],
+                    &bin_str[16..32]
+                )
+            }
+            op if is_fpu_instruction(op) => {
+                format!(
+                    "{} {} {} {} {} {}",
+                    &bin_str[0..6],
+                    &bin_str[6..11],
+                    &bin_str[11..16],
+                    &bin_str[16..21],
+                    &bin_str[21..26],
+                    &bin_str[26..32]
+                )
+            }
+            "j" | "jal" => {
                 format!("{} {}", &bin_str[0..6], &bin_str[6..32])
             }
             _ => bin_str,
@@ -268,11 +736,8 @@ impl MipsSimulator {
     }
 
     fn instruction_fetch(&mut self) -> Option<u32> {
-        if let Some(&binary_instruction) = self.binary_program.get(self.pc as usize) {
-            Some(binary_instruction)
-        } else {
-            None
-        }
+        let index = *self.instruction_index.get(&self.pc)?;
+        self.binary_program.get(index).copied()
     }
 
     fn instruction_decode(&self, binary_instruction: u32) -> DecodedInstruction {
@@ -280,6 +745,7 @@ impl MipsSimulator {
         let rs = ((binary_instruction >> 21) & 0x1F) as u8;
         let rt = ((binary_instruction >> 16) & 0x1F) as u8;
         let rd = ((binary_instruction >> 11) & 0x1F) as u8;
+        let shamt = ((binary_instruction >> 6) & 0x1F) as u8;
         let funct = (binary_instruction & 0x3F) as u8;
         let immediate = (binary_instruction & 0xFFFF) as u16;
         let address = binary_instruction & 0x3FFFFFF;
@@ -288,6 +754,7 @@ impl MipsSimulator {
             rs,
             rt,
             rd,
+            shamt,
             funct,
             immediate,
             address,
@@ -300,26 +767,150 @@ impl MipsSimulator {
             0 => {
                 let funct = decoded.funct;
                 match funct {
+                    0 => self.execute_sll(decoded),
+                    2 if decoded.rs == 1 => self.execute_rotr(decoded),
+                    2 => self.execute_srl(decoded),
+                    3 => self.execute_sra(decoded),
+                    4 => self.execute_sllv(decoded),
+                    6 if decoded.shamt == 1 => self.execute_rotrv(decoded),
+                    6 => self.execute_srlv(decoded),
+                    7 => self.execute_srav(decoded),
+                    8 => {
+                        self.execute_jr(decoded);
+                        return true;
+                    }
+                    9 => {
+                        self.execute_jalr(decoded);
+                        return true;
+                    }
+                    12 => self.execute_syscall(),
+                    13 => self.raise_exception(Exception::Breakpoint),
+                    10 => self.execute_movz(decoded),
+                    11 => self.execute_movn(decoded),
+                    16 => self.registers[decoded.rd as usize] = self.hi,
+                    17 => self.hi = self.registers[decoded.rs as usize],
+                    18 => self.registers[decoded.rd as usize] = self.lo,
+                    19 => self.lo = self.registers[decoded.rs as usize],
+                    24 => self.execute_mult(decoded),
+                    25 => self.execute_multu(decoded),
+                    26 => self.execute_div(decoded),
+                    27 => self.execute_divu(decoded),
+                    28..=31 | 45 | 47 | 56 | 58..=60 | 62 | 63 => self.execute_special64(decoded),
                     32 => self.execute_add(decoded),
+                    33 => self.execute_addu(decoded),
                     34 => self.execute_sub(decoded),
+                    35 => self.execute_subu(decoded),
                     36 => self.execute_and(decoded),
                     37 => self.execute_or(decoded),
+                    38 => self.execute_xor(decoded),
+                    39 => self.execute_nor(decoded),
                     42 => self.execute_slt(decoded),
-                    _ => println!("Unknown funct code: {}", funct),
+                    43 => self.execute_sltu(decoded),
+                    48..=51 | 52 | 54 => self.execute_trap(decoded),
+                    _ => self.raise_exception(Exception::ReservedInstruction),
+                }
+            }
+            28 => {
+                let funct = decoded.funct;
+                match funct {
+                    0 => self.execute_madd(decoded),
+                    1 => self.execute_maddu(decoded),
+                    2 => self.execute_mul(decoded),
+                    4 => self.execute_msub(decoded),
+                    5 => self.execute_msubu(decoded),
+                    32 => self.execute_clz(decoded),
+                    33 => self.execute_clo(decoded),
+                    _ => self.raise_exception(Exception::ReservedInstruction),
+                }
+            }
+            16 => return self.execute_cop0(decoded),
+            17 => return self.execute_cop1(decoded),
+            31 => {
+                let funct = decoded.funct;
+                match funct {
+                    0 => self.execute_ext(decoded),
+                    4 => self.execute_ins(decoded),
+                    32 => match decoded.shamt {
+                        2 => self.execute_wsbh(decoded),
+                        16 => self.execute_seb(decoded),
+                        24 => self.execute_seh(decoded),
+                        _ => self.raise_exception(Exception::ReservedInstruction),
+                    },
+                    _ => self.raise_exception(Exception::ReservedInstruction),
                 }
             }
+            49 => self.execute_lwc1(decoded),
+            53 => self.execute_ldc1(decoded),
+            57 => self.execute_swc1(decoded),
+            61 => self.execute_sdc1(decoded),
+            25 => self.execute_daddiu(decoded),
+            39 => self.execute_lwu(decoded),
+            55 => self.execute_ld(decoded),
+            63 => self.execute_sd(decoded),
             8 => self.execute_addi(decoded),
+            9 => self.execute_addiu(decoded),
+            10 => self.execute_slti(decoded),
+            11 => self.execute_sltiu(decoded),
+            12 => self.execute_andi(decoded),
+            13 => self.execute_ori(decoded),
+            14 => self.execute_xori(decoded),
+            15 => self.execute_lui(decoded),
+            32 => self.execute_lb(decoded),
+            33 => self.execute_lh(decoded),
+            34 => self.execute_lwl(decoded),
             35 => self.execute_lw(decoded),
+            36 => self.execute_lbu(decoded),
+            37 => self.execute_lhu(decoded),
+            38 => self.execute_lwr(decoded),
+            40 => self.execute_sb(decoded),
+            41 => self.execute_sh(decoded),
+            42 => self.execute_swl(decoded),
             43 => self.execute_sw(decoded),
+            46 => self.execute_swr(decoded),
+            48 => self.execute_ll(decoded),
+            56 => self.execute_sc(decoded),
+            1 => {
+                match decoded.rt {
+                    0 => self.execute_bltz(decoded),
+                    1 => self.execute_bgez(decoded),
+                    16 => self.execute_bltzal(decoded),
+                    17 => self.execute_bgezal(decoded),
+                    8..=12 | 14 => {
+                        self.execute_trap_immediate(decoded);
+                        return false;
+                    }
+                    _ => {
+                        self.raise_exception(Exception::ReservedInstruction);
+                        return false;
+                    }
+                }
+                return true;
+            }
             4 => {
                 self.execute_beq(decoded);
                 return true;
             }
+            5 => {
+                self.execute_bne(decoded);
+                return true;
+            }
+            6 => {
+                self.execute_blez(decoded);
+                return true;
+            }
+            7 => {
+                self.execute_bgtz(decoded);
+                return true;
+            }
             2 => {
                 self.execute_j(decoded);
                 return true;
             }
-            _ => println!("Unknown opcode: {}", opcode),
+            3 => {
+                self.execute_jal(decoded);
+                return true;
+            }
+            _ => self.raise_exception(Exception::ReservedInstruction),
         }
         false
     }
@@ -328,14 +919,36 @@ impl MipsSimulator {
         let rs = decoded.rs as usize;
         let rt = decoded.rt as usize;
         let rd = decoded.rd as usize;
-        self.registers[rd] = self.registers[rs] + self.registers[rt];
+        match (self.registers[rs] as i32).checked_add(self.registers[rt] as i32) {
+            Some(result) => self.registers[rd] = result as i64,
+            None => self.raise_exception(Exception::Overflow),
+        }
     }
 
     fn execute_sub(&mut self, decoded: DecodedInstruction) {
         let rs = decoded.rs as usize;
         let rt = decoded.rt as usize;
         let rd = decoded.rd as usize;
-        self.registers[rd] = self.registers[rs] - self.registers[rt];
+        match (self.registers[rs] as i32).checked_sub(self.registers[rt] as i32) {
+            Some(result) => self.registers[rd] = result as i64,
+            None => self.raise_exception(Exception::Overflow),
+        }
+    }
+
+    fn execute_addu(&mut self, decoded: DecodedInstruction) {
+        let rs = decoded.rs as usize;
+        let rt = decoded.rt as usize;
+        let rd = decoded.rd as usize;
+        self.registers[rd] =
+            (self.registers[rs] as i32).wrapping_add(self.registers[rt] as i32) as i64;
+    }
+
+    fn execute_subu(&mut self, decoded: DecodedInstruction) {
+        let rs = decoded.rs as usize;
+        let rt = decoded.rt as usize;
+        let rd = decoded.rd as usize;
+        self.registers[rd] =
+            (self.registers[rs] as i32).wrapping_sub(self.registers[rt] as i32) as i64;
     }
 
     fn execute_and(&mut self, decoded: DecodedInstruction) {
@@ -352,6 +965,38 @@ impl MipsSimulator {
         self.registers[rd] = self.registers[rs] | self.registers[rt];
     }
 
+    fn execute_xor(&mut self, decoded: DecodedInstruction) {
+        let rs = decoded.rs as usize;
+        let rt = decoded.rt as usize;
+        let rd = decoded.rd as usize;
+        self.registers[rd] = self.registers[rs] ^ self.registers[rt];
+    }
+
+    fn execute_nor(&mut self, decoded: DecodedInstruction) {
+        let rs = decoded.rs as usize;
+        let rt = decoded.rt as usize;
+        let rd = decoded.rd as usize;
+        self.registers[rd] = !(self.registers[rs] | self.registers[rt]);
+    }
+
+    fn execute_movz(&mut self, decoded: DecodedInstruction) {
+        let rs = decoded.rs as usize;
+        let rt = decoded.rt as usize;
+        let rd = decoded.rd as usize;
+        if self.registers[rt] == 0 {
+            self.registers[rd] = self.registers[rs];
+        }
+    }
+
+    fn execute_movn(&mut self, decoded: DecodedInstruction) {
+        let rs = decoded.rs as usize;
+        let rt = decoded.rt as usize;
+        let rd = decoded.rd as usize;
+        if self.registers[rt] != 0 {
+            self.registers[rd] = self.registers[rs];
+        }
+    }
+
     fn execute_slt(&mut self, decoded: DecodedInstruction) {
         let rs = decoded.rs as usize;
         let rt = decoded.rt as usize;
@@ -363,89 +1008,776 @@ impl MipsSimulator {
         };
     }
 
-    fn execute_addi(&mut self, decoded: DecodedInstruction) {
+    fn execute_sltu(&mut self, decoded: DecodedInstruction) {
         let rs = decoded.rs as usize;
         let rt = decoded.rt as usize;
-        let immediate = decoded.immediate as i16 as i32;
-        self.registers[rt] = self.registers[rs] + immediate;
+        let rd = decoded.rd as usize;
+        self.registers[rd] = if (self.registers[rs] as u64) < (self.registers[rt] as u64) {
+            1
+        } else {
+            0
+        };
     }
 
-    fn execute_lw(&mut self, decoded: DecodedInstruction) {
-        let base = decoded.rs as usize;
+    fn execute_sll(&mut self, decoded: DecodedInstruction) {
         let rt = decoded.rt as usize;
-        let offset = decoded.immediate as i16 as i32;
-        let address = (self.registers[base] + offset) as u32;
-        if let Some(&value) = self.memory.get(&address) {
-            self.registers[rt] = value;
-        } else {
-            println!("Memory read error at address: 0x{:08X}", address);
-        }
+        let rd = decoded.rd as usize;
+        self.registers[rd] = ((self.registers[rt] as u32) << decoded.shamt) as i32 as i64;
     }
 
-    fn execute_sw(&mut self, decoded: DecodedInstruction) {
-        let base = decoded.rs as usize;
+    fn execute_srl(&mut self, decoded: DecodedInstruction) {
         let rt = decoded.rt as usize;
-        let offset = decoded.immediate as i16 as i32;
-        let address = (self.registers[base] + offset) as u32;
-        let value = self.registers[rt];
-        self.memory.insert(address, value);
+        let rd = decoded.rd as usize;
+        self.registers[rd] = ((self.registers[rt] as u32) >> decoded.shamt) as i32 as i64;
     }
 
-    fn execute_beq(&mut self, decoded: DecodedInstruction) {
+    fn execute_sra(&mut self, decoded: DecodedInstruction) {
+        let rt = decoded.rt as usize;
+        let rd = decoded.rd as usize;
+        self.registers[rd] = (self.registers[rt] as i32 >> decoded.shamt) as i64;
+    }
+
+    fn execute_sllv(&mut self, decoded: DecodedInstruction) {
         let rs = decoded.rs as usize;
         let rt = decoded.rt as usize;
-        let offset = decoded.immediate as i16 as i32;
-        if self.registers[rs] == self.registers[rt] {
-            self.pc = ((self.pc as i32) + offset + 1) as u32;
-        } else {
-            self.pc += 1;
-        }
+        let rd = decoded.rd as usize;
+        let shamt = self.registers[rs] as u32 & 0x1F;
+        self.registers[rd] = ((self.registers[rt] as u32) << shamt) as i32 as i64;
     }
 
-    fn execute_j(&mut self, decoded: DecodedInstruction) {
-        self.pc = decoded.address;
+    fn execute_srlv(&mut self, decoded: DecodedInstruction) {
+        let rs = decoded.rs as usize;
+        let rt = decoded.rt as usize;
+        let rd = decoded.rd as usize;
+        let shamt = self.registers[rs] as u32 & 0x1F;
+        self.registers[rd] = ((self.registers[rt] as u32) >> shamt) as i32 as i64;
     }
 
-    fn get_register_index(&self, register: &str) -> usize {
-        *self.reg_map.get(register).unwrap_or_else(|| {
-            println!("Unknown register: {}", register);
-            &0
-        })
+    fn execute_srav(&mut self, decoded: DecodedInstruction) {
+        let rs = decoded.rs as usize;
+        let rt = decoded.rt as usize;
+        let rd = decoded.rd as usize;
+        let shamt = self.registers[rs] as u32 & 0x1F;
+        self.registers[rd] = (self.registers[rt] as i32 >> shamt) as i64;
     }
 
-    fn get_register_name(&self, index: usize) -> String {
-        self.reg_map_rev
-            .get(&index)
-            .cloned()
-            .unwrap_or_else(|| format!("${}", index))
+    fn execute_mult(&mut self, decoded: DecodedInstruction) {
+        let rs = decoded.rs as usize;
+        let rt = decoded.rt as usize;
+        let product = self.registers[rs] as i32 as i64 * self.registers[rt] as i32 as i64;
+        self.set_hi_lo(product);
     }
 
-    fn run(&mut self) {
-        while (self.pc as usize) < self.binary_program.len() {
-            if let Some(binary_instruction) = self.instruction_fetch() {
-                let decoded = self.instruction_decode(binary_instruction);
-                let pc_modified = self.execute_instruction(decoded);
-                if !pc_modified {
-                    self.pc += 1;
-                }
-            } else {
-                break;
-            }
-        }
+    fn execute_multu(&mut self, decoded: DecodedInstruction) {
+        let rs = decoded.rs as usize;
+        let rt = decoded.rt as usize;
+        let product = self.registers[rs] as u32 as u64 * self.registers[rt] as u32 as u64;
+        self.set_hi_lo(product as i64);
     }
 
-    fn print_registers(&self) {
-        println!("Registers:");
-        for (i, reg) in self.registers.iter().enumerate() {
-            let reg_name = self.get_register_name(i);
-            println!("{} ({:>2}): {}", reg_name, i, reg);
-        }
+    fn execute_mul(&mut self, decoded: DecodedInstruction) {
+        let rs = decoded.rs as usize;
+        let rt = decoded.rt as usize;
+        let rd = decoded.rd as usize;
+        self.registers[rd] =
+            (self.registers[rs] as i32).wrapping_mul(self.registers[rt] as i32) as i64;
     }
 
-    fn print_memory(&self) {
-        println!("Memory:");
-        for (address, value) in &self.memory {
-            let mut label_name = String::new();
+    // The 32-bit multiply and divide group keeps a 64-bit result split across
+    // the low words of HI and LO, each sign-extended.
+    fn hi_lo(&self) -> i64 {
+        ((self.hi as i32 as i64) << 32) | (self.lo as u32 as i64)
+    }
+
+    fn set_hi_lo(&mut self, value: i64) {
+        self.hi = (value >> 32) as i32 as i64;
+        self.lo = value as i32 as i64;
+    }
+
+    fn execute_madd(&mut self, decoded: DecodedInstruction) {
+        let rs = decoded.rs as usize;
+        let rt = decoded.rt as usize;
+        let product = self.registers[rs] as i32 as i64 * self.registers[rt] as i32 as i64;
+        self.set_hi_lo(self.hi_lo().wrapping_add(product));
+    }
+
+    fn execute_maddu(&mut self, decoded: DecodedInstruction) {
+        let rs = decoded.rs as usize;
+        let rt = decoded.rt as usize;
+        let product = self.registers[rs] as u32 as u64 * self.registers[rt] as u32 as u64;
+        self.set_hi_lo((self.hi_lo() as u64).wrapping_add(product) as i64);
+    }
+
+    fn execute_msub(&mut self, decoded: DecodedInstruction) {
+        let rs = decoded.rs as usize;
+        let rt = decoded.rt as usize;
+        let product = self.registers[rs] as i32 as i64 * self.registers[rt] as i32 as i64;
+        self.set_hi_lo(self.hi_lo().wrapping_sub(product));
+    }
+
+    fn execute_msubu(&mut self, decoded: DecodedInstruction) {
+        let rs = decoded.rs as usize;
+        let rt = decoded.rt as usize;
+        let product = self.registers[rs] as u32 as u64 * self.registers[rt] as u32 as u64;
+        self.set_hi_lo((self.hi_lo() as u64).wrapping_sub(product) as i64);
+    }
+
+    fn execute_clz(&mut self, decoded: DecodedInstruction) {
+        let rs = decoded.rs as usize;
+        let rd = decoded.rd as usize;
+        self.registers[rd] = (self.registers[rs] as i32).leading_zeros() as i64;
+    }
+
+    fn execute_clo(&mut self, decoded: DecodedInstruction) {
+        let rs = decoded.rs as usize;
+        let rd = decoded.rd as usize;
+        self.registers[rd] = (self.registers[rs] as i32).leading_ones() as i64;
+    }
+
+    // Dividing by zero leaves HI and LO unpredictable on real hardware; here
+    // they simply keep their previous contents.
+    fn execute_div(&mut self, decoded: DecodedInstruction) {
+        let rs = decoded.rs as usize;
+        let rt = decoded.rt as usize;
+        let dividend = self.registers[rs] as i32;
+        let divisor = self.registers[rt] as i32;
+        if divisor != 0 {
+            self.lo = dividend.wrapping_div(divisor) as i64;
+            self.hi = dividend.wrapping_rem(divisor) as i64;
+        }
+    }
+
+    fn execute_divu(&mut self, decoded: DecodedInstruction) {
+        let rs = decoded.rs as usize;
+        let rt = decoded.rt as usize;
+        let dividend = self.registers[rs] as u32;
+        let divisor = self.registers[rt] as u32;
+        if let (Some(quotient), Some(remainder)) =
+            (dividend.checked_div(divisor), dividend.checked_rem(divisor))
+        {
+            self.lo = quotient as i32 as i64;
+            self.hi = remainder as i32 as i64;
+        }
+    }
+
+    fn execute_rotr(&mut self, decoded: DecodedInstruction) {
+        let rt = decoded.rt as usize;
+        let rd = decoded.rd as usize;
+        self.registers[rd] =
+            (self.registers[rt] as u32).rotate_right(decoded.shamt as u32) as i32 as i64;
+    }
+
+    fn execute_rotrv(&mut self, decoded: DecodedInstruction) {
+        let rs = decoded.rs as usize;
+        let rt = decoded.rt as usize;
+        let rd = decoded.rd as usize;
+        let shamt = self.registers[rs] as u32 & 0x1F;
+        self.registers[rd] = (self.registers[rt] as u32).rotate_right(shamt) as i32 as i64;
+    }
+
+    fn execute_ext(&mut self, decoded: DecodedInstruction) {
+        let rs = decoded.rs as usize;
+        let rt = decoded.rt as usize;
+        let pos = decoded.shamt as u32;
+        let size = decoded.rd as u32 + 1;
+        if pos + size > 32 {
+            self.raise_exception(Exception::ReservedInstruction);
+            return;
+        }
+        let mask = u32::MAX >> (32 - size);
+        self.registers[rt] = ((self.registers[rs] as u32 >> pos) & mask) as i32 as i64;
+    }
+
+    fn execute_ins(&mut self, decoded: DecodedInstruction) {
+        let rs = decoded.rs as usize;
+        let rt = decoded.rt as usize;
+        let lsb = decoded.shamt as u32;
+        let msb = decoded.rd as u32;
+        if msb < lsb {
+            self.raise_exception(Exception::ReservedInstruction);
+            return;
+        }
+        let mask = (u32::MAX >> (31 - msb + lsb)) << lsb;
+        let inserted = ((self.registers[rs] as u32) << lsb) & mask;
+        self.registers[rt] = ((self.registers[rt] as u32 & !mask) | inserted) as i32 as i64;
+    }
+
+    fn execute_wsbh(&mut self, decoded: DecodedInstruction) {
+        let rt = decoded.rt as usize;
+        let rd = decoded.rd as usize;
+        let value = self.registers[rt] as u32;
+        self.registers[rd] =
+            (((value & 0x00FF_00FF) << 8) | ((value >> 8) & 0x00FF_00FF)) as i32 as i64;
+    }
+
+    fn execute_seb(&mut self, decoded: DecodedInstruction) {
+        let rt = decoded.rt as usize;
+        let rd = decoded.rd as usize;
+        self.registers[rd] = self.registers[rt] as i8 as i64;
+    }
+
+    fn execute_seh(&mut self, decoded: DecodedInstruction) {
+        let rt = decoded.rt as usize;
+        let rd = decoded.rd as usize;
+        self.registers[rd] = self.registers[rt] as i16 as i64;
+    }
+
+    fn execute_addi(&mut self, decoded: DecodedInstruction) {
+        let rs = decoded.rs as usize;
+        let rt = decoded.rt as usize;
+        let immediate = decoded.immediate as i16 as i32;
+        match (self.registers[rs] as i32).checked_add(immediate) {
+            Some(result) => self.registers[rt] = result as i64,
+            None => self.raise_exception(Exception::Overflow),
+        }
+    }
+
+    fn execute_addiu(&mut self, decoded: DecodedInstruction) {
+        let rs = decoded.rs as usize;
+        let rt = decoded.rt as usize;
+        let immediate = decoded.immediate as i16 as i32;
+        self.registers[rt] = (self.registers[rs] as i32).wrapping_add(immediate) as i64;
+    }
+
+    fn execute_slti(&mut self, decoded: DecodedInstruction) {
+        let rs = decoded.rs as usize;
+        let rt = decoded.rt as usize;
+        let immediate = decoded.immediate as i16 as i64;
+        self.registers[rt] = if self.registers[rs] < immediate { 1 } else { 0 };
+    }
+
+    fn execute_sltiu(&mut self, decoded: DecodedInstruction) {
+        let rs = decoded.rs as usize;
+        let rt = decoded.rt as usize;
+        // The immediate is sign-extended first and only then compared as unsigned.
+        let immediate = decoded.immediate as i16 as i64 as u64;
+        self.registers[rt] = if (self.registers[rs] as u64) < immediate {
+            1
+        } else {
+            0
+        };
+    }
+
+    fn execute_andi(&mut self, decoded: DecodedInstruction) {
+        let rs = decoded.rs as usize;
+        let rt = decoded.rt as usize;
+        let immediate = decoded.immediate as i64;
+        self.registers[rt] = self.registers[rs] & immediate;
+    }
+
+    fn execute_ori(&mut self, decoded: DecodedInstruction) {
+        let rs = decoded.rs as usize;
+        let rt = decoded.rt as usize;
+        let immediate = decoded.immediate as i64;
+        self.registers[rt] = self.registers[rs] | immediate;
+    }
+
+    fn execute_xori(&mut self, decoded: DecodedInstruction) {
+        let rs = decoded.rs as usize;
+        let rt = decoded.rt as usize;
+        let immediate = decoded.immediate as i64;
+        self.registers[rt] = self.registers[rs] ^ immediate;
+    }
+
+    fn execute_lui(&mut self, decoded: DecodedInstruction) {
+        let rt = decoded.rt as usize;
+        self.registers[rt] = ((decoded.immediate as u32) << 16) as i32 as i64;
+    }
+
+    // The full virtual address; check_address narrows it to the 32-bit space
+    // the simulator's memory covers.
+    fn effective_address(&self, decoded: &DecodedInstruction) -> u64 {
+        let base = decoded.rs as usize;
+        let offset = decoded.immediate as i16 as i64;
+        if self.mips64 {
+            self.registers[base].wrapping_add(offset) as u64
+        } else {
+            (self.registers[base] as i32).wrapping_add(offset as i32) as u64
+        }
+    }
+
+    fn execute_lb(&mut self, decoded: DecodedInstruction) {
+        let rt = decoded.rt as usize;
+        let address = self.effective_address(&decoded);
+        let Some(address) = self.check_address(address, 1, false) else {
+            return;
+        };
+        self.set_loaded_register(rt, self.read_byte(address) as i8 as i64);
+    }
+
+    fn execute_lbu(&mut self, decoded: DecodedInstruction) {
+        let rt = decoded.rt as usize;
+        let address = self.effective_address(&decoded);
+        let Some(address) = self.check_address(address, 1, false) else {
+            return;
+        };
+        self.set_loaded_register(rt, self.read_byte(address) as i64);
+    }
+
+    fn execute_lh(&mut self, decoded: DecodedInstruction) {
+        let rt = decoded.rt as usize;
+        let address = self.effective_address(&decoded);
+        if let Some(address) = self.check_address(address, 2, false) {
+            self.set_loaded_register(rt, self.read_half(address) as i16 as i64);
+        }
+    }
+
+    fn execute_lhu(&mut self, decoded: DecodedInstruction) {
+        let rt = decoded.rt as usize;
+        let address = self.effective_address(&decoded);
+        if let Some(address) = self.check_address(address, 2, false) {
+            self.set_loaded_register(rt, self.read_half(address) as i64);
+        }
+    }
+
+    fn execute_lw(&mut self, decoded: DecodedInstruction) {
+        let rt = decoded.rt as usize;
+        let address = self.effective_address(&decoded);
+        if let Some(address) = self.check_address(address, 4, false) {
+            self.set_loaded_register(rt, self.read_word(address) as i32 as i64);
+        }
+    }
+
+    // In MIPS I mode a loaded value only reaches the register once the next
+    // instruction has executed.
+    fn set_loaded_register(&mut self, rt: usize, value: i64) {
+        if self.load_delay_slots {
+            self.pending_load = Some((rt, value));
+        } else {
+            self.registers[rt] = value;
+        }
+    }
+
+    // lwl and lwr merge into a load that is still in flight to the same
+    // register, so an lwl/lwr pair works back to back in MIPS I mode too.
+    fn load_merge_source(&self, rt: usize) -> i64 {
+        match self.delayed_load {
+            Some((register, value)) if register == rt => value,
+            _ => self.registers[rt],
+        }
+    }
+
+    fn execute_sb(&mut self, decoded: DecodedInstruction) {
+        let rt = decoded.rt as usize;
+        let address = self.effective_address(&decoded);
+        let Some(address) = self.check_address(address, 1, true) else {
+            return;
+        };
+        self.write_byte(address, self.registers[rt] as u8);
+    }
+
+    fn execute_sh(&mut self, decoded: DecodedInstruction) {
+        let rt = decoded.rt as usize;
+        let address = self.effective_address(&decoded);
+        if let Some(address) = self.check_address(address, 2, true) {
+            self.write_half(address, self.registers[rt] as u16);
+        }
+    }
+
+    fn execute_sw(&mut self, decoded: DecodedInstruction) {
+        let rt = decoded.rt as usize;
+        let address = self.effective_address(&decoded);
+        if let Some(address) = self.check_address(address, 4, true) {
+            self.write_word(address, self.registers[rt] as u32);
+        }
+    }
+
+    fn execute_ll(&mut self, decoded: DecodedInstruction) {
+        let rt = decoded.rt as usize;
+        let address = self.effective_address(&decoded);
+        if let Some(address) = self.check_address(address, 4, false) {
+            self.registers[rt] = self.read_word(address) as i32 as i64;
+            self.ll_address = address;
+            self.ll_bit = true;
+        }
+    }
+
+    // sc only stores if nothing has broken the link since the matching ll, and
+    // reports the outcome in rt.
+    fn execute_sc(&mut self, decoded: DecodedInstruction) {
+        let rt = decoded.rt as usize;
+        let address = self.effective_address(&decoded);
+        if let Some(address) = self.check_address(address, 4, true) {
+            let linked = self.ll_bit && self.ll_address == address;
+            if linked {
+                self.write_word(address, self.registers[rt] as u32);
+            }
+            self.ll_bit = false;
+            self.registers[rt] = linked as i64;
+        }
+    }
+
+    // Significance of the addressed byte within its aligned word: 3 is the most
+    // significant byte, 0 the least. This is where endianness enters lwl/lwr/swl/swr.
+    fn byte_significance(&self, address: u32) -> u32 {
+        if self.big_endian {
+            3 - (address & 3)
+        } else {
+            address & 3
+        }
+    }
+
+    // lwl moves the addressed byte and the less significant bytes of its word
+    // into the most significant end of rt.
+    fn execute_lwl(&mut self, decoded: DecodedInstruction) {
+        let rt = decoded.rt as usize;
+        let address = self.effective_address(&decoded);
+        let Some(address) = self.check_address(address, 1, false) else {
+            return;
+        };
+        let word = self.read_word(address & !3);
+        let shift = 8 * (3 - self.byte_significance(address));
+        let kept = self.load_merge_source(rt) as u32 & ((1u32 << shift) - 1);
+        self.set_loaded_register(rt, (kept | (word << shift)) as i32 as i64);
+    }
+
+    // lwr moves the addressed byte and the more significant bytes of its word
+    // into the least significant end of rt.
+    fn execute_lwr(&mut self, decoded: DecodedInstruction) {
+        let rt = decoded.rt as usize;
+        let address = self.effective_address(&decoded);
+        let Some(address) = self.check_address(address, 1, false) else {
+            return;
+        };
+        let word = self.read_word(address & !3);
+        let shift = 8 * self.byte_significance(address);
+        let kept = self.load_merge_source(rt) as u32 & !(u32::MAX >> shift);
+        self.set_loaded_register(rt, (kept | (word >> shift)) as i32 as i64);
+    }
+
+    fn execute_swl(&mut self, decoded: DecodedInstruction) {
+        let rt = decoded.rt as usize;
+        let address = self.effective_address(&decoded);
+        let Some(address) = self.check_address(address, 1, true) else {
+            return;
+        };
+        let word = self.read_word(address & !3);
+        let shift = 8 * (3 - self.byte_significance(address));
+        let kept = word & !(u32::MAX >> shift);
+        self.write_word(address & !3, kept | (self.registers[rt] as u32 >> shift));
+    }
+
+    fn execute_swr(&mut self, decoded: DecodedInstruction) {
+        let rt = decoded.rt as usize;
+        let address = self.effective_address(&decoded);
+        let Some(address) = self.check_address(address, 1, true) else {
+            return;
+        };
+        let word = self.read_word(address & !3);
+        let shift = 8 * self.byte_significance(address);
+        let kept = word & ((1u32 << shift) - 1);
+        self.write_word(address & !3, kept | ((self.registers[rt] as u32) << shift));
+    }
+
+    fn execute_trap(&mut self, decoded: DecodedInstruction) {
+        let rs = self.registers[decoded.rs as usize];
+        let rt = self.registers[decoded.rt as usize];
+        let condition = match decoded.funct {
+            48 => rs >= rt,
+            49 => rs as u64 >= rt as u64,
+            50 => rs < rt,
+            51 => (rs as u64) < rt as u64,
+            52 => rs == rt,
+            _ => rs != rt,
+        };
+        if condition {
+            self.raise_exception(Exception::Trap);
+        }
+    }
+
+    fn execute_trap_immediate(&mut self, decoded: DecodedInstruction) {
+        let rs = self.registers[decoded.rs as usize];
+        let immediate = decoded.immediate as i16 as i64;
+        let condition = match decoded.rt {
+            8 => rs >= immediate,
+            9 => rs as u64 >= immediate as u64,
+            10 => rs < immediate,
+            11 => (rs as u64) < immediate as u64,
+            12 => rs == immediate,
+            _ => rs != immediate,
+        };
+        if condition {
+            self.raise_exception(Exception::Trap);
+        }
+    }
+
+    fn execute_beq(&mut self, decoded: DecodedInstruction) {
+        let rs = decoded.rs as usize;
+        let rt = decoded.rt as usize;
+        let taken = self.registers[rs] == self.registers[rt];
+        self.branch(taken, decoded);
+    }
+
+    fn execute_bne(&mut self, decoded: DecodedInstruction) {
+        let rs = decoded.rs as usize;
+        let rt = decoded.rt as usize;
+        let taken = self.registers[rs] != self.registers[rt];
+        self.branch(taken, decoded);
+    }
+
+    fn execute_blez(&mut self, decoded: DecodedInstruction) {
+        let taken = self.registers[decoded.rs as usize] <= 0;
+        self.branch(taken, decoded);
+    }
+
+    fn execute_bgtz(&mut self, decoded: DecodedInstruction) {
+        let taken = self.registers[decoded.rs as usize] > 0;
+        self.branch(taken, decoded);
+    }
+
+    fn execute_bltz(&mut self, decoded: DecodedInstruction) {
+        let taken = self.registers[decoded.rs as usize] < 0;
+        self.branch(taken, decoded);
+    }
+
+    fn execute_bgez(&mut self, decoded: DecodedInstruction) {
+        let taken = self.registers[decoded.rs as usize] >= 0;
+        self.branch(taken, decoded);
+    }
+
+    // The link register is written whether or not the branch is taken.
+    fn execute_bltzal(&mut self, decoded: DecodedInstruction) {
+        let taken = self.registers[decoded.rs as usize] < 0;
+        self.registers[31] = self.link_address();
+        self.branch(taken, decoded);
+    }
+
+    fn execute_bgezal(&mut self, decoded: DecodedInstruction) {
+        let taken = self.registers[decoded.rs as usize] >= 0;
+        self.registers[31] = self.link_address();
+        self.branch(taken, decoded);
+    }
+
+    fn branch(&mut self, taken: bool, decoded: DecodedInstruction) {
+        let offset = decoded.immediate as i16 as i32;
+        if taken {
+            self.jump_to((self.pc as i32 + 4 + (offset << 2)) as u32);
+        } else {
+            self.pc += 4;
+        }
+    }
+
+    // Branch and jump targets are relative to the delay slot at pc + 4. With
+    // delay slots enabled the slot executes first and run() then jumps.
+    fn jump_to(&mut self, target: u32) {
+        if self.delay_slots {
+            self.pending_branch = Some(target);
+            self.pc += 4;
+        } else {
+            self.pc = target;
+        }
+    }
+
+    // The return address skips over the delay slot when there is one.
+    fn link_address(&self) -> i64 {
+        let skip = if self.delay_slots { 8 } else { 4 };
+        (self.pc + skip) as i32 as i64
+    }
+
+    fn execute_j(&mut self, decoded: DecodedInstruction) {
+        self.jump_to(((self.pc + 4) & 0xF000_0000) | (decoded.address << 2));
+    }
+
+    fn execute_jal(&mut self, decoded: DecodedInstruction) {
+        self.registers[31] = self.link_address();
+        self.execute_j(decoded);
+    }
+
+    fn execute_jr(&mut self, decoded: DecodedInstruction) {
+        self.jump_to(self.registers[decoded.rs as usize] as u32);
+    }
+
+    // The target is read before the link register is written, so `jalr $ra`
+    // still jumps to the old return address.
+    fn execute_jalr(&mut self, decoded: DecodedInstruction) {
+        let target = self.registers[decoded.rs as usize] as u32;
+        self.registers[decoded.rd as usize] = self.link_address();
+        self.jump_to(target);
+    }
+
+    // Memory is byte addressable; multi-byte values follow `big_endian`.
+    // Bytes that were never written read back as zero.
+    fn read_byte(&self, address: u32) -> u8 {
+        *self.memory.get(&address).unwrap_or(&0)
+    }
+
+    fn write_byte(&mut self, address: u32, value: u8) {
+        // Any store to the linked word breaks an outstanding ll/sc sequence.
+        if self.ll_bit && address & !3 == self.ll_address {
+            self.ll_bit = false;
+        }
+        self.memory.insert(address, value);
+    }
+
+    fn read_half(&self, address: u32) -> u16 {
+        let bytes = [
+            self.read_byte(address),
+            self.read_byte(address.wrapping_add(1)),
+        ];
+        if self.big_endian {
+            u16::from_be_bytes(bytes)
+        } else {
+            u16::from_le_bytes(bytes)
+        }
+    }
+
+    fn write_half(&mut self, address: u32, value: u16) {
+        let bytes = if self.big_endian {
+            value.to_be_bytes()
+        } else {
+            value.to_le_bytes()
+        };
+        for (i, byte) in bytes.into_iter().enumerate() {
+            self.write_byte(address.wrapping_add(i as u32), byte);
+        }
+    }
+
+    fn read_word(&self, address: u32) -> u32 {
+        let bytes = [
+            self.read_byte(address),
+            self.read_byte(address.wrapping_add(1)),
+            self.read_byte(address.wrapping_add(2)),
+            self.read_byte(address.wrapping_add(3)),
+        ];
+        if self.big_endian {
+            u32::from_be_bytes(bytes)
+        } else {
+            u32::from_le_bytes(bytes)
+        }
+    }
+
+    fn write_word(&mut self, address: u32, value: u32) {
+        let bytes = if self.big_endian {
+            value.to_be_bytes()
+        } else {
+            value.to_le_bytes()
+        };
+        for (i, byte) in bytes.into_iter().enumerate() {
+            self.write_byte(address.wrapping_add(i as u32), byte);
+        }
+    }
+
+    fn read_doubleword(&self, address: u32) -> u64 {
+        let first = self.read_word(address) as u64;
+        let second = self.read_word(address.wrapping_add(4)) as u64;
+        if self.big_endian {
+            (first << 32) | second
+        } else {
+            (second << 32) | first
+        }
+    }
+
+    fn write_doubleword(&mut self, address: u32, value: u64) {
+        let (first, second) = if self.big_endian {
+            ((value >> 32) as u32, value as u32)
+        } else {
+            (value as u32, (value >> 32) as u32)
+        };
+        self.write_word(address, first);
+        self.write_word(address.wrapping_add(4), second);
+    }
+
+    fn report_error(&self, message: &str) {
+        self.report_error_at(message, &self.source_location);
+    }
+
+    fn report_error_at(&self, message: &str, location: &str) {
+        if location.is_empty() {
+            println!("Error: {}", message);
+        } else {
+            println!("Error: {} at {}", message, location);
+        }
+    }
+
+    fn get_register_index(&self, register: &str) -> usize {
+        *self.reg_map.get(register).unwrap_or_else(|| {
+            self.report_error(&format!("Unknown register {}", register));
+            &0
+        })
+    }
+
+    fn get_register_name(&self, index: usize) -> String {
+        self.reg_map_rev
+            .get(&index)
+            .cloned()
+            .unwrap_or_else(|| format!("${}", index))
+    }
+
+    fn run(&mut self) {
+        loop {
+            self.exception_raised = false;
+            // A branch or MIPS I load issued by the previous instruction
+            // completes after this one.
+            self.delay_slot_target = self.pending_branch.take();
+            self.delayed_load = self.pending_load.take();
+            self.check_interrupts();
+            if !self.exception_raised
+                && self
+                    .check_address(self.pc as i32 as u64, 4, false)
+                    .is_some()
+            {
+                let Some(binary_instruction) = self.instruction_fetch() else {
+                    // A load in the last instruction still reaches its register.
+                    if let Some((rt, value)) = self.delayed_load.take() {
+                        self.registers[rt] = value;
+                    }
+                    break;
+                };
+                let decoded = self.instruction_decode(binary_instruction);
+                self.registers.written = 0;
+                let pc_modified = self.execute_instruction(decoded);
+                if !pc_modified && !self.exception_raised {
+                    self.pc = self.pc.wrapping_add(4);
+                }
+                self.tick_timer();
+                // The delayed load loses to a later write of the same register,
+                // whether by this instruction or by a load it issued.
+                if let Some((rt, _)) = self.delayed_load {
+                    let overwritten = self.registers.written & (1 << rt) != 0
+                        || self.pending_load.is_some_and(|(pending, _)| pending == rt);
+                    if overwritten {
+                        self.delayed_load = None;
+                    }
+                }
+            }
+            if let Some((rt, value)) = self.delayed_load.take() {
+                self.registers[rt] = value;
+            }
+            self.registers[0] = 0;
+            // raise_exception drops the target of a branch whose slot faulted.
+            if let Some(target) = self.delay_slot_target.take() {
+                self.pc = target;
+            }
+            if self.exit_code.is_some() {
+                break;
+            }
+        }
+        self.output.flush().expect("Could not write program output");
+
+        // Without an exception handler loaded, the first exception ends the run.
+        if self.pc == EXCEPTION_VECTOR {
+            self.report_unhandled_exception();
+        }
+    }
+
+    fn print_registers(&self) {
+        println!("Registers:");
+        for (i, reg) in self.registers.values.iter().enumerate() {
+            let reg_name = self.get_register_name(i);
+            println!("{} ({:>2}): {}", reg_name, i, reg);
+        }
+        println!("$hi     : {}", self.hi);
+        println!("$lo     : {}", self.lo);
+        self.print_fpu_registers();
+        self.print_cp0_registers();
+    }
+
+    fn print_memory(&self) {
+        println!("Memory:");
+        let word_addresses: BTreeSet<u32> = self.memory.keys().map(|&a| a & !3).collect();
+        for address in &word_addresses {
+            let value = self.read_word(*address) as i32;
+            let mut label_name = String::new();
             for (label, &addr) in &self.labels {
                 if addr == *address {
                     label_name = label.clone();
@@ -455,21 +1787,1413 @@ impl MipsSimulator {
             if label_name.is_empty() {
                 println!("Address: 0x{:08X}, Value: {}", address, value);
             } else {
-                println!("Address: 0x{:08X} ({}), Value: {}", address, label_name, value);
+                println!(
+                    "Address: 0x{:08X} ({}), Value: {}",
+                    address, label_name, value
+                );
             }
         }
     }
 }
 
+// The value following a command-line flag, e.g. `--stdin input.txt`.
+fn argument_value(flag: &str) -> Option<String> {
+    let args: Vec<String> = std::env::args().collect();
+    let position = args.iter().position(|arg| arg == flag)?;
+    args.get(position + 1).cloned()
+}
+
+// Every argument that is neither a flag nor a flag's value names a source
+// file or a single ELF executable. Source files are assembled separately and
+// linked in the order given.
+fn source_files() -> Vec<String> {
+    let mut files = vec![];
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        if matches!(
+            arg.as_str(),
+            "--stdin"
+                | "--stdout"
+                | "--sandbox"
+                | "--elf"
+                | "--dump-text"
+                | "--dump-data"
+                | "--dump-format"
+                | "--dump-base"
+                | "--dump-endian"
+        ) {
+            args.next();
+        } else if !arg.starts_with("--") {
+            files.push(arg);
+        }
+    }
+    if files.is_empty() {
+        files.push("{put your filename here}".to_string());
+    }
+    files
+}
+
+// `--dump-text FILE` and `--dump-data FILE` export a segment for hardware
+// tools, in the `--dump-format` given (hextext by default), in the byte order
+// of `--dump-endian big|little`, and for a memory that starts at
+// `--dump-base ADDRESS`.
+fn dump_segments(simulator: &MipsSimulator) {
+    let format_name = argument_value("--dump-format").unwrap_or("hextext".to_string());
+    let Some(format) = DumpFormat::from_name(&format_name) else {
+        simulator.report_error(&format!("Unknown dump format {}", format_name));
+        return;
+    };
+    let big_endian = match argument_value("--dump-endian").as_deref() {
+        Some("big") => true,
+        Some("little") => false,
+        _ => simulator.big_endian,
+    };
+    let base = argument_value("--dump-base").map(|base| simulator.immediate_operand(&base) as u32);
+    for (flag, segment) in [
+        ("--dump-text", Segment::Text),
+        ("--dump-data", Segment::Data),
+    ] {
+        let Some(path) = argument_value(flag) else {
+            continue;
+        };
+        match simulator.dump_segment(segment, format, big_endian, base) {
+            Ok(bytes) => std::fs::write(&path, bytes).expect("Could not write dump file"),
+            Err(message) => simulator.report_error(&message),
+        }
+    }
+}
+
 fn main() {
-    let mut simulator = MipsSimulator::new();
+    let mut simulator = if std::env::args().any(|arg| arg == "--mips64") {
+        MipsSimulator::new_mips64()
+    } else {
+        MipsSimulator::new()
+    };
+    if let Some(path) = argument_value("--stdin") {
+        simulator.set_input(BufReader::new(
+            File::open(path).expect("Could not open input file"),
+        ));
+    }
+    if let Some(path) = argument_value("--stdout") {
+        simulator.set_output(File::create(path).expect("Could not create output file"));
+    }
+    simulator.delay_slots = std::env::args().any(|arg| arg == "--delay-slots");
+    simulator.load_delay_slots = std::env::args().any(|arg| arg == "--load-delay-slots");
+    if std::env::args().any(|arg| arg == "--linux") {
+        simulator.abi = SyscallAbi::LinuxO32;
+        simulator.sandbox = argument_value("--sandbox").map(PathBuf::from);
+    }
 
-    simulator.load_program_from_file("{put your filename here}");
+    simulator.big_endian = std::env::args().any(|arg| arg == "--big-endian");
+    simulator.objects = std::env::args().any(|arg| arg == "--objects");
+    // An executable is already linked, so it is run on its own.
+    let files = source_files();
+    if files.len() > 1 && files.iter().any(|filename| is_elf_file(filename)) {
+        simulator.report_error("An ELF executable cannot be loaded together with other files");
+        std::process::exit(1);
+    }
+    for filename in files {
+        if is_elf_file(&filename) {
+            simulator.load_elf(&filename);
+        } else {
+            simulator.load_program_from_file(&filename);
+        }
+    }
 
     simulator.assemble_program();
 
+    // `--elf out` writes the linked executable; `--objects` writes each
+    // source file's unit next to it as a relocatable .o file.
+    if let Some(path) = argument_value("--elf") {
+        simulator
+            .write_elf_executable(&path)
+            .expect("Could not write ELF executable");
+    }
+    // `--listing` writes a listing, symbol table and cross-reference next to
+    // the first source file.
+    if let (true, Some(unit)) = (
+        std::env::args().any(|arg| arg == "--listing"),
+        simulator.units.first(),
+    ) {
+        let path = std::path::Path::new(&unit.name).with_extension("lst");
+        std::fs::write(path, simulator.listing()).expect("Could not write listing");
+    }
+    if simulator.objects {
+        for unit in &simulator.units {
+            let path = std::path::Path::new(&unit.name).with_extension("o");
+            simulator
+                .write_elf_object(unit, &path.to_string_lossy())
+                .expect("Could not write ELF object");
+        }
+        // Objects that still need linking cannot run on their own.
+        if simulator
+            .units
+            .iter()
+            .any(|unit| !unit.undefined.is_empty())
+        {
+            return;
+        }
+    }
+
+    dump_segments(&simulator);
+
     simulator.run();
 
     simulator.print_registers();
     simulator.print_memory();
+
+    if let Some(code) = simulator.exit_code {
+        std::process::exit(code);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    const BASE: u32 = 0x1000;
+
+    fn simulator_with_bytes(big_endian: bool) -> MipsSimulator {
+        let mut simulator = MipsSimulator::new();
+        simulator.big_endian = big_endian;
+        for i in 0..8 {
+            simulator.write_byte(BASE + i, 0x11 * (i as u8 + 1));
+        }
+        simulator
+    }
+
+    // Program output that stays readable after a clone is given to the simulator.
+    #[derive(Clone, Default)]
+    struct OutputBuffer(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for OutputBuffer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.borrow_mut().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    // Places one instruction per line from the start of the text segment
+    // and assembles them against the simulator's current labels.
+    fn assemble_lines(simulator: &mut MipsSimulator, lines: &[impl AsRef<str>]) {
+        simulator.program = lines
+            .iter()
+            .enumerate()
+            .map(|(i, line)| (TEXT_BASE + 4 * i as u32, line.as_ref().to_string()))
+            .collect();
+        simulator.assemble_program();
+    }
+
+    fn run_lines(simulator: &mut MipsSimulator, lines: &[impl AsRef<str>]) {
+        assemble_lines(simulator, lines);
+        simulator.run();
+    }
+
+    #[test]
+    fn divide_keeps_hi_lo_on_zero_and_wraps_the_most_negative_quotient() {
+        let lines = [
+            "lui $t0, 0x8000",
+            "addi $t1, $zero, -1",
+            "div $t0, $t1",
+            "mflo $s0",
+            "mfhi $s1",
+            "addi $t2, $zero, 7",
+            "addi $t3, $zero, -2",
+            "div $t2, $t3",
+            "div $t2, $zero",
+            "mflo $s2",
+            "mfhi $s3",
+            "divu $t1, $t3",
+            "divu $t2, $zero",
+            "mflo $s4",
+            "mfhi $s5",
+            "multu $t1, $t1",
+            "mfhi $s6",
+            "mult $t1, $t1",
+            "mfhi $s7",
+        ];
+        let mut simulator = MipsSimulator::new();
+        run_lines(&mut simulator, &lines);
+        let registers = &simulator.registers.values[16..24];
+        assert_eq!(registers, [-0x8000_0000, 0, -3, 1, 1, 1, -2, 0]);
+        assert_eq!(simulator.lo, 1);
+    }
+
+    #[test]
+    fn logical_immediates_zero_extend_and_sltiu_compares_unsigned() {
+        let lines = [
+            "addi $t0, $zero, -1",
+            "andi $s0, $t0, 0xFFFF",
+            "ori $s1, $zero, 0x8000",
+            "xori $s2, $t0, 0x8000",
+            "lui $s3, 0xFFFF",
+            "sltiu $s4, $t0, -1",
+            "sltiu $s5, $zero, -1",
+            "sltiu $s6, $t0, 1",
+            "slti $s7, $t0, 0",
+            "sltu $t1, $zero, $t0",
+            "addiu $t2, $t0, 1",
+            "subu $t3, $zero, $t0",
+            "addu $t4, $t0, $t0",
+        ];
+        let mut simulator = MipsSimulator::new();
+        run_lines(&mut simulator, &lines);
+        let registers = &simulator.registers.values[16..24];
+        assert_eq!(registers, [0xFFFF, 0x8000, -0x8001, -0x1_0000, 0, 1, 0, 1]);
+        assert_eq!(simulator.registers.values[9..13], [1, 0, 1, -2]);
+    }
+
+    #[test]
+    fn calls_link_the_return_address_whether_or_not_they_branch() {
+        let source = std::env::temp_dir().join(format!("calls-{}.s", std::process::id()));
+        std::fs::write(
+            &source,
+            "addi $t0, $zero, -1\nbltzal $t0, taken\naddi $s0, $zero, 1\n\
+             taken: add $s1, $ra, $zero\nbgezal $t0, taken\n\
+             not_taken: add $s2, $ra, $zero\njal function\n\
+             returned: la $t1, target\njalr $s4, $t1\naddi $s0, $zero, 2\n\
+             target: addi $v0, $zero, 10\nsyscall\n\
+             function: add $s3, $ra, $zero\njr $ra\n",
+        )
+        .unwrap();
+        let mut simulator = MipsSimulator::new();
+        simulator.load_program_from_file(&source.to_string_lossy());
+        std::fs::remove_file(&source).unwrap();
+        simulator.assemble_program();
+        simulator.run();
+
+        let label = |name: &str| simulator.labels[name] as i64;
+        assert_eq!(simulator.registers[16], 0);
+        assert_eq!(simulator.registers[17], label("taken") - 4);
+        assert_eq!(simulator.registers[18], label("not_taken"));
+        assert_eq!(simulator.registers[19], label("returned"));
+        assert_eq!(simulator.registers[20], label("target") - 4);
+        assert_eq!(simulator.exit_code, Some(0));
+    }
+
+    #[test]
+    fn narrow_loads_extend_and_misaligned_halves_fault() {
+        let lines = [
+            "addi $t0, $zero, 4096",
+            "addi $t1, $zero, -32640",
+            "sh $t1, 0($t0)",
+            "sb $t1, 2($t0)",
+            "lb $s0, 0($t0)",
+            "lbu $s1, 0($t0)",
+            "lb $s2, 1($t0)",
+            "lh $s3, 0($t0)",
+            "lhu $s4, 0($t0)",
+            "lb $s5, 2($t0)",
+        ];
+        let mut simulator = MipsSimulator::new();
+        run_lines(&mut simulator, &lines);
+        let registers = &simulator.registers.values[16..22];
+        assert_eq!(registers, [-128, 0x80, -128, -32640, 0x8080, -128]);
+
+        for (line, exception, address) in [
+            ("lh $s0, 1($t0)", Exception::AddressErrorLoad, 0x1001),
+            ("lhu $s0, 3($t0)", Exception::AddressErrorLoad, 0x1003),
+            ("sh $s0, 1($t0)", Exception::AddressErrorStore, 0x1001),
+        ] {
+            let mut simulator = MipsSimulator::new();
+            run_lines(&mut simulator, &["addi $t0, $zero, 4096", line]);
+            assert_eq!(simulator.pc, EXCEPTION_VECTOR, "{}", line);
+            assert_eq!((simulator.cp0[cp0::CAUSE] >> 2) & 0x1F, exception as u32);
+            assert_eq!(simulator.cp0[cp0::BAD_VADDR], address);
+        }
+    }
+
+    #[test]
+    fn special2_accumulates_into_hi_lo_and_counts_leading_bits() {
+        let lines = [
+            "addi $t0, $zero, -3",
+            "addi $t1, $zero, 5",
+            "mul $s0, $t0, $t1",
+            "mthi $zero",
+            "mtlo $zero",
+            "madd $t0, $t1",
+            "madd $t0, $t1",
+            "msub $t1, $t1",
+            "mfhi $s1",
+            "mflo $s2",
+            "maddu $t0, $t1",
+            "mfhi $s3",
+            "msubu $t0, $t1",
+            "mflo $s4",
+            "clz $s5, $t1",
+            "clo $s6, $t0",
+            "clz $s7, $zero",
+            "nor $t2, $t0, $t1",
+            "xor $t3, $t0, $t1",
+            "movn $t4, $t1, $zero",
+            "movz $t5, $t1, $zero",
+        ];
+        let mut simulator = MipsSimulator::new();
+        run_lines(&mut simulator, &lines);
+        let registers = &simulator.registers.values[16..24];
+        assert_eq!(registers, [-15, -1, -55, 4, -55, 29, 30, 32]);
+        assert_eq!(simulator.registers.values[10..14], [2, -8, 0, 5]);
+    }
+
+    #[test]
+    fn lwl_lwr_pair_matches_byte_by_byte_load() {
+        for (big_endian, load_delay_slots) in [(false, false), (true, false), (false, true)] {
+            for offset in 0..4 {
+                let address = BASE + offset;
+                let mut lines = vec![format!("addi $t0, $zero, {}", address)];
+                for k in 0..4 {
+                    let significance = if big_endian { 3 - k } else { k };
+                    lines.push(format!("lbu $t1, {}($t0)", k));
+                    if load_delay_slots {
+                        lines.push("sll $zero, $zero, 0".to_string());
+                    }
+                    lines.push(format!("sll $t1, $t1, {}", 8 * significance));
+                    lines.push("or $s0, $s0, $t1".to_string());
+                }
+                lines.push("addi $s1, $zero, -1".to_string());
+                if big_endian {
+                    lines.push("lwl $s1, 0($t0)".to_string());
+                    lines.push("lwr $s1, 3($t0)".to_string());
+                } else {
+                    lines.push("lwr $s1, 0($t0)".to_string());
+                    lines.push("lwl $s1, 3($t0)".to_string());
+                }
+
+                let mut simulator = simulator_with_bytes(big_endian);
+                simulator.load_delay_slots = load_delay_slots;
+                run_lines(&mut simulator, &lines);
+
+                let bytes: Vec<u8> = (0..4).map(|k| simulator.read_byte(address + k)).collect();
+                let expected = if big_endian {
+                    u32::from_be_bytes(bytes.try_into().unwrap())
+                } else {
+                    u32::from_le_bytes(bytes.try_into().unwrap())
+                };
+                assert_eq!(simulator.registers[16] as u32, expected);
+                assert_eq!(simulator.registers[17], simulator.registers[16]);
+            }
+        }
+    }
+
+    #[test]
+    fn swl_swr_pair_matches_byte_by_byte_store() {
+        for big_endian in [false, true] {
+            for offset in 0..4 {
+                let address = BASE + offset;
+                let mut lines = vec![
+                    format!("addi $t0, $zero, {}", address),
+                    "lui $s0, 4660".to_string(),
+                    "ori $s0, $s0, 22136".to_string(),
+                ];
+                if big_endian {
+                    lines.push("swl $s0, 0($t0)".to_string());
+                    lines.push("swr $s0, 3($t0)".to_string());
+                } else {
+                    lines.push("swr $s0, 0($t0)".to_string());
+                    lines.push("swl $s0, 3($t0)".to_string());
+                }
+
+                let mut simulator = simulator_with_bytes(big_endian);
+                run_lines(&mut simulator, &lines);
+
+                let expected = if big_endian {
+                    0x12345678u32.to_be_bytes()
+                } else {
+                    0x12345678u32.to_le_bytes()
+                };
+                for k in 0..4 {
+                    assert_eq!(simulator.read_byte(address + k), expected[k as usize]);
+                }
+                // Bytes either side of the unaligned word are left alone.
+                assert_eq!(simulator.read_byte(BASE + 7), 0x88);
+                if offset > 0 {
+                    assert_eq!(simulator.read_byte(BASE), 0x11);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn branches_reject_targets_out_of_range_or_misaligned() {
+        let mut simulator = MipsSimulator::new();
+        for (label, address) in [
+            ("far", TEXT_BASE + 4 + 4 * 32768),
+            ("edge", TEXT_BASE + 8 + 4 * 32767),
+            ("odd", TEXT_BASE + 6),
+            ("back", TEXT_BASE + 16 - 4 * 32768),
+        ] {
+            simulator.labels.insert(label.to_string(), address);
+        }
+        let lines = [
+            "beq $zero, $zero, far",
+            "beq $zero, $zero, edge",
+            "beq $zero, $zero, odd",
+            "bne $zero, $zero, back",
+        ];
+        assemble_lines(&mut simulator, &lines);
+        assert_eq!(simulator.binary_program, [0, 0x1000_7FFF, 0, 0x1400_8000]);
+    }
+
+    #[test]
+    fn release_2_instructions_swap_extend_insert_and_rotate() {
+        let lines = [
+            "lui $t0, 0x1234",
+            "ori $t0, $t0, 0x56F8",
+            "wsbh $s0, $t0",
+            "seb $s1, $t0",
+            "seh $s2, $t0",
+            "ext $s3, $t0, 4, 8",
+            "addi $s4, $zero, -1",
+            "ins $s4, $zero, 8, 16",
+            "rotr $s5, $t0, 8",
+            "addi $t1, $zero, 4",
+            "rotrv $s6, $t0, $t1",
+        ];
+        let mut simulator = MipsSimulator::new();
+        run_lines(&mut simulator, &lines);
+        let registers = &simulator.registers.values[16..23];
+        assert_eq!(
+            registers,
+            [
+                0x3412_F856,
+                -8,
+                0x56F8,
+                0x6F,
+                -0x00FF_FF01,
+                -0x07ED_CBAA,
+                -0x7EDC_BA91,
+            ]
+        );
+    }
+
+    #[test]
+    fn shift_amounts_must_fit_in_five_bits() {
+        let lines = [
+            "sll $t0, $t1, 31",
+            "sll $t0, $t1, 32",
+            "sra $t0, $t1, -1",
+            "rotr $t0, $t1, 32",
+        ];
+        let mut simulator = MipsSimulator::new();
+        assemble_lines(&mut simulator, &lines);
+        assert_eq!(
+            simulator.binary_program,
+            [0x0009_47C0, 0x0009_4000, 0x0009_4003, 0x0029_4002]
+        );
+    }
+
+    #[test]
+    fn bit_fields_must_fit_in_a_word() {
+        let lines = [
+            "ins $t0, $t1, 20, 16",
+            "ext $t0, $t1, 4, 0",
+            "ins $t0, $t1, 16, 16",
+            "ext $t0, $t1, 0, 32",
+        ];
+        let mut simulator = MipsSimulator::new();
+        assemble_lines(&mut simulator, &lines);
+        assert_eq!(simulator.binary_program, [0, 0, 0x7D28_FC04, 0x7D28_F800]);
+    }
+
+    #[test]
+    fn delay_slot_write_of_the_same_value_beats_the_delayed_load() {
+        let lines = [
+            "addi $t1, $zero, 4096",
+            "addi $t0, $zero, 7",
+            "lw $t0, 0($t1)",
+            "addi $t0, $zero, 7",
+            "add $s0, $t0, $zero",
+            "lw $t2, 0($t1)",
+            "add $s1, $t2, $zero",
+            "add $s2, $t2, $zero",
+        ];
+        let mut simulator = MipsSimulator::new();
+        simulator.load_delay_slots = true;
+        simulator.write_word(BASE, 5);
+        run_lines(&mut simulator, &lines);
+        assert_eq!(simulator.registers[16], 7);
+        assert_eq!(simulator.registers[17], 0);
+        assert_eq!(simulator.registers[18], 5);
+    }
+
+    #[test]
+    fn syscalls_use_redirected_input_and_output() {
+        let lines = [
+            "addi $v0, $zero, 5",
+            "syscall",
+            "add $a0, $v0, $v0",
+            "addi $v0, $zero, 1",
+            "syscall",
+            "addi $a0, $zero, 10",
+            "addi $v0, $zero, 11",
+            "syscall",
+            "addi $a0, $zero, 40",
+            "addi $v0, $zero, 9",
+            "syscall",
+            "add $s0, $v0, $zero",
+            "add $a0, $v0, $zero",
+            "addi $a1, $zero, 4",
+            "addi $v0, $zero, 8",
+            "syscall",
+            "addi $v0, $zero, 4",
+            "syscall",
+            "addi $v0, $zero, 12",
+            "syscall",
+            "add $s1, $v0, $zero",
+            "addi $a0, $zero, 3",
+            "addi $v0, $zero, 17",
+            "syscall",
+            "addi $s2, $zero, 1",
+        ];
+
+        let output = OutputBuffer::default();
+        let mut simulator = MipsSimulator::new();
+        simulator.set_input(std::io::Cursor::new("-21\nhello\nx"));
+        simulator.set_output(output.clone());
+        run_lines(&mut simulator, &lines);
+
+        assert_eq!(output.0.borrow().as_slice(), b"-42\nhel");
+        assert_eq!(simulator.registers[16], 0x1000);
+        assert_eq!(simulator.read_byte(0x1003), 0);
+        assert_eq!(simulator.registers[17], b'x' as i64);
+        assert_eq!(simulator.exit_code, Some(3));
+        assert_eq!(simulator.registers[18], 0);
+    }
+
+    #[test]
+    fn delay_slot_executes_before_the_jump() {
+        let lines = [
+            "jal f",
+            "addi $t0, $zero, 1",
+            "beq $zero, $zero, end",
+            "addi $t1, $zero, 2",
+            "jr $ra",
+            "addi $t2, $zero, 3",
+            "addi $t3, $zero, 4",
+        ];
+        let mut simulator = MipsSimulator::new();
+        simulator.delay_slots = true;
+        simulator.labels.insert("f".to_string(), TEXT_BASE + 16);
+        simulator.labels.insert("end".to_string(), TEXT_BASE + 24);
+        run_lines(&mut simulator, &lines);
+        // Each slot ran, and jal linked past its own slot.
+        assert_eq!(simulator.registers.values[8..12], [1, 2, 3, 4]);
+        assert_eq!(simulator.registers[31], TEXT_BASE as i64 + 8);
+    }
+
+    #[test]
+    fn mips64_sign_extends_words_and_reserves_doublewords_in_mips32() {
+        let lines = [
+            "lui $t0, 0x7FFF",
+            "ori $t0, $t0, 0xFFFF",
+            "addiu $t1, $t0, 1",
+            "dsll32 $t2, $t0, 0",
+            "dsra32 $t3, $t2, 4",
+            "daddu $t4, $t2, $t0",
+            "addi $t5, $zero, 4096",
+            "sd $t1, 0($t5)",
+            "sd $t4, 8($t5)",
+            "ld $s0, 0($t5)",
+            "lwu $s1, 0($t5)",
+            "lw $s2, 0($t5)",
+            "ld $s3, 8($t5)",
+        ];
+        let mut simulator = MipsSimulator::new_mips64();
+        run_lines(&mut simulator, &lines);
+        assert_eq!(simulator.registers[9], -0x8000_0000);
+        assert_eq!(simulator.registers[10], 0x7FFF_FFFF_0000_0000);
+        assert_eq!(simulator.registers[11], 0x07FF_FFFF);
+        assert_eq!(simulator.registers[12], 0x7FFF_FFFF_7FFF_FFFF);
+        assert_eq!(simulator.registers[16], -0x8000_0000);
+        assert_eq!(simulator.registers[17], 0x8000_0000);
+        assert_eq!(simulator.registers[18], -0x8000_0000);
+        assert_eq!(simulator.registers[19], 0x7FFF_FFFF_7FFF_FFFF);
+
+        for line in ["dsll32 $t2, $t0, 0", "ld $s0, 0($t5)"] {
+            let mut simulator = MipsSimulator::new();
+            run_lines(&mut simulator, &[line]);
+            assert_eq!(simulator.pc, EXCEPTION_VECTOR);
+            assert_eq!(
+                (simulator.cp0[cp0::CAUSE] >> 2) & 0x1F,
+                Exception::ReservedInstruction as u32
+            );
+        }
+
+        // dsll32 and friends shift by 32 more; their field is still 0..=31.
+        let mut simulator = MipsSimulator::new_mips64();
+        assemble_lines(
+            &mut simulator,
+            &["dsll $t2, $t0, 32", "dsra32 $t3, $t2, -1"],
+        );
+        assert_eq!(simulator.binary_program, [0x0008_5038, 0x000A_583F]);
+    }
+
+    #[test]
+    fn fpu_arithmetic_rounding_and_condition_flags() {
+        let source = std::env::temp_dir().join(format!("fpu-{}.s", std::process::id()));
+        std::fs::write(
+            &source,
+            ".data\na: .float 2.5\nb: .float -1.5\nd: .double 3.75\n.text\n\
+             lwc1 $f0, a\nlwc1 $f1, b\nadd.s $f2, $f0, $f1\nmul.s $f3, $f0, $f1\n\
+             ldc1 $f4, d\ndiv.d $f6, $f4, $f4\ncvt.w.s $f8, $f0\ncvt.w.s $f9, $f1\n\
+             c.lt.s 3, $f1, $f0\nc.eq.s $f0, $f1\n\
+             bc1t 3, yes\naddi $s0, $zero, 1\n\
+             yes: bc1f no\naddi $s1, $zero, 1\n\
+             no: bc1t 2, never\naddi $s2, $zero, 1\n\
+             never: mfc1 $s3, $f8\nmfc1 $s4, $f9\n",
+        )
+        .unwrap();
+        // Round to nearest even, toward zero, up and down.
+        for (mode, expected) in [(0, [2, -2]), (1, [2, -1]), (2, [3, -1]), (3, [2, -2])] {
+            let mut simulator = MipsSimulator::new();
+            simulator.fcsr = mode;
+            simulator.load_program_from_file(&source.to_string_lossy());
+            simulator.assemble_program();
+            simulator.run();
+
+            assert_eq!(f32::from_bits(simulator.fpr[2]), 1.0);
+            assert_eq!(f32::from_bits(simulator.fpr[3]), -3.75);
+            let quotient = ((simulator.fpr[7] as u64) << 32) | simulator.fpr[6] as u64;
+            assert_eq!(f64::from_bits(quotient), 1.0);
+            assert_eq!(simulator.registers.values[16..19], [0, 0, 1]);
+            assert_eq!(simulator.registers.values[19..21], expected);
+        }
+        std::fs::remove_file(&source).unwrap();
+    }
+
+    #[test]
+    fn exceptions_record_epc_cause_and_delay_slot_then_eret() {
+        let source = std::env::temp_dir().join(format!("exceptions-{}.s", std::process::id()));
+        std::fs::write(
+            &source,
+            ".text\nlui $t0, 0x7FFF\nori $t0, $t0, 0xFFFF\naddi $t1, $zero, 3\n\
+             add $t1, $t0, $t0\nbeq $zero, $zero, skip\nadd $t2, $t0, $t0\n\
+             addi $s4, $zero, 1\nskip: addi $s5, $zero, 1\naddi $v0, $zero, 10\nsyscall\n\
+             .ktext\nmfc0 $k0, $13\nmfc0 $k1, $14\nbnez $s7, second\nnop\n\
+             move $s0, $k0\nmove $s1, $k1\naddiu $k1, $k1, 4\nb done\nnop\n\
+             # A fault in a delay slot resumes after the slot.\n\
+             second: move $s2, $k0\nmove $s3, $k1\naddiu $k1, $k1, 8\n\
+             done: addi $s7, $s7, 1\nmtc0 $k1, $14\neret\n",
+        )
+        .unwrap();
+        let mut simulator = MipsSimulator::new();
+        simulator.delay_slots = true;
+        simulator.load_program_from_file(&source.to_string_lossy());
+        std::fs::remove_file(&source).unwrap();
+        simulator.assemble_program();
+        simulator.run();
+
+        let cause_bits = |register: usize| simulator.registers[register] as u32 & 0x8000_007C;
+        assert_eq!(simulator.registers[23], 2);
+        assert_eq!(cause_bits(16), (Exception::Overflow as u32) << 2);
+        assert_eq!(simulator.registers[17], TEXT_BASE as i64 + 12);
+        assert_eq!(
+            cause_bits(18),
+            (1 << 31) | (Exception::Overflow as u32) << 2
+        );
+        assert_eq!(simulator.registers[19], TEXT_BASE as i64 + 16);
+        // The faulting adds wrote nothing.
+        assert_eq!(simulator.registers[9], 3);
+        assert_eq!(simulator.registers[10], 0);
+        assert_eq!(simulator.registers[20], 1);
+        assert_eq!(simulator.registers[21], 1);
+        assert_eq!(simulator.cp0[STATUS] & 2, 0);
+        assert_eq!(simulator.exit_code, Some(0));
+    }
+
+    #[test]
+    fn sc_stores_only_while_the_ll_link_holds() {
+        let sc_after = |between: &[&str]| {
+            let mut lines = vec![
+                "addi $t1, $zero, 4096",
+                "addi $t2, $zero, 5",
+                "sw $t2, 0($t1)",
+                "ll $t0, 0($t1)",
+                "addi $t0, $t0, 1",
+            ];
+            lines.extend(between);
+            lines.push("sc $t0, 0($t1)");
+            let mut simulator = MipsSimulator::new();
+            run_lines(&mut simulator, &lines);
+            (simulator.registers[8], simulator.read_word(BASE))
+        };
+        assert_eq!(sc_after(&[]), (1, 6));
+        assert_eq!(sc_after(&["sw $t2, 4($t1)"]), (1, 6));
+        assert_eq!(sc_after(&["sw $t2, 0($t1)"]), (0, 5));
+        assert_eq!(sc_after(&["sb $zero, 3($t1)"]), (0, 5));
+
+        // eret resumes at the sc, which then fails.
+        let mut simulator = MipsSimulator::new();
+        let lines = [
+            "addi $t1, $zero, 4096",
+            "lui $k0, 0x40",
+            "ori $k0, $k0, 24",
+            "mtc0 $k0, $14",
+            "ll $t0, 0($t1)",
+            "eret",
+            "sc $t0, 0($t1)",
+        ];
+        run_lines(&mut simulator, &lines);
+        assert_eq!(simulator.pc, TEXT_BASE + 28);
+        assert_eq!(simulator.registers[8], 0);
+
+        let mut simulator = MipsSimulator::new();
+        run_lines(&mut simulator, &["ll $t0, 4096($zero)"]);
+        assert!(simulator.ll_bit);
+        let mut simulator = MipsSimulator::new();
+        run_lines(&mut simulator, &["ll $t0, 4096($zero)", "teq $zero, $zero"]);
+        assert_eq!(simulator.pc, EXCEPTION_VECTOR);
+        assert!(!simulator.ll_bit);
+    }
+
+    #[test]
+    fn traps_compare_signed_unsigned_and_immediate_operands() {
+        // $t0 is -1 and $t1 is 1; unsigned comparisons see $t0 as the larger.
+        let cases = [
+            ("teq $t0, $t0", true),
+            ("tne $t0, $t0", false),
+            ("tge $t0, $t1", false),
+            ("tgeu $t0, $t1", true),
+            ("tlt $t0, $t1", true),
+            ("tltu $t0, $t1", false),
+            ("teqi $t0, -1", true),
+            ("tnei $t0, -1", false),
+            ("tgei $t0, 0", false),
+            ("tgeiu $t1, -1", false),
+            ("tlti $t0, 0", true),
+            ("tltiu $t1, -1", true),
+        ];
+        for (line, traps) in cases {
+            let mut simulator = MipsSimulator::new();
+            run_lines(
+                &mut simulator,
+                &["addi $t0, $zero, -1", "addi $t1, $zero, 1", line],
+            );
+            let expected = if traps {
+                EXCEPTION_VECTOR
+            } else {
+                TEXT_BASE + 12
+            };
+            assert_eq!(simulator.pc, expected, "{}", line);
+            if traps {
+                assert_eq!(simulator.cp0[cp0::EPC], TEXT_BASE + 8, "{}", line);
+                assert_eq!(
+                    (simulator.cp0[cp0::CAUSE] >> 2) & 0x1F,
+                    Exception::Trap as u32
+                );
+            }
+        }
+
+        let mut simulator = MipsSimulator::new();
+        assemble_lines(
+            &mut simulator,
+            &["teq $t0, $t1, 1023", "teq $t0, $t1, 1024", "break 0x100000"],
+        );
+        assert_eq!(simulator.binary_program, [0x0109_FFF4, 0x0109_0034, 13]);
+    }
+
+    #[test]
+    fn unhandled_exceptions_report_the_faulting_instruction() {
+        let mut simulator = MipsSimulator::new();
+        run_lines(&mut simulator, &["addi $t0, $zero, 1", "teq $t0, $t0, 7"]);
+        assert_eq!(
+            simulator.unhandled_exception_report(),
+            "Unhandled exception: trap (code 13) at PC 0x00400004\n\
+             Faulting instruction: teq $t0, $t0, 7 [0x010801F4]\n"
+        );
+
+        let mut simulator = MipsSimulator::new();
+        run_lines(&mut simulator, &["lw $t0, 4097($zero)"]);
+        assert_eq!(
+            simulator.unhandled_exception_report(),
+            "Unhandled exception: address error on load or fetch (code 4) at PC 0x00400000\n\
+             Faulting instruction: lw $t0, 4097($zero) [0x8C081001]\n\
+             Bad virtual address: 0x00001001\n"
+        );
+    }
+
+    #[test]
+    fn linux_syscalls_follow_the_o32_convention() {
+        let lines = [
+            "addi $a0, $zero, -100",
+            "addi $a1, $zero, 4096",
+            "addi $a2, $zero, 0",
+            "addi $v0, $zero, 4288",
+            "syscall",
+            "add $s0, $v0, $zero",
+            "add $a0, $v0, $zero",
+            "addi $a1, $zero, 4352",
+            "addi $a2, $zero, 16",
+            "addi $v0, $zero, 4003",
+            "syscall",
+            "addi $a0, $zero, 1",
+            "add $a2, $v0, $zero",
+            "addi $v0, $zero, 4004",
+            "syscall",
+            "addi $a0, $zero, -100",
+            "addi $a1, $zero, 4160",
+            "addi $a2, $zero, 0",
+            "addi $v0, $zero, 4288",
+            "syscall",
+            "add $s1, $v0, $zero",
+            "add $s2, $a3, $zero",
+            "add $a0, $s0, $zero",
+            "addi $v0, $zero, 4006",
+            "syscall",
+            "addi $a1, $zero, 100",
+            "addi $a3, $zero, 2050",
+            "addi $v0, $zero, 4090",
+            "syscall",
+            "add $s3, $v0, $zero",
+            "add $s4, $a3, $zero",
+            "addi $a0, $zero, 263",
+            "addi $v0, $zero, 4246",
+            "syscall",
+        ];
+
+        let sandbox = std::env::temp_dir().join(format!("sandbox_{}", std::process::id()));
+        std::fs::create_dir_all(&sandbox).unwrap();
+        std::fs::write(sandbox.join("in.txt"), "from a file").unwrap();
+
+        let output = OutputBuffer::default();
+        let mut simulator = MipsSimulator::new();
+        simulator.abi = SyscallAbi::LinuxO32;
+        simulator.sandbox = Some(sandbox.clone());
+        simulator.set_output(output.clone());
+        for (address, path) in [(BASE, "/in.txt"), (BASE + 0x40, "../in.txt")] {
+            for (i, byte) in path.bytes().enumerate() {
+                simulator.write_byte(address + i as u32, byte);
+            }
+        }
+        run_lines(&mut simulator, &lines);
+        std::fs::remove_dir_all(&sandbox).unwrap();
+
+        assert_eq!(simulator.registers[16], 3);
+        assert_eq!(output.0.borrow().as_slice(), b"from a file");
+        assert_eq!(simulator.registers[17], 13);
+        assert_eq!(simulator.registers[18], 1);
+        assert!(simulator.open_files.is_empty());
+        assert_eq!(simulator.registers[19], MMAP_BASE as i64);
+        assert_eq!(simulator.registers[20], 0);
+        assert_eq!(simulator.exit_code, Some(7));
+    }
+
+    #[test]
+    fn linux_read_of_a_huge_count_is_a_short_read() {
+        let lines = [
+            "addi $a0, $zero, 0",
+            "addi $a1, $zero, 4096",
+            "addi $a2, $zero, -1",
+            "addi $v0, $zero, 4003",
+            "syscall",
+            "add $s0, $v0, $zero",
+        ];
+        let mut simulator = MipsSimulator::new();
+        simulator.abi = SyscallAbi::LinuxO32;
+        simulator.set_input(std::io::Cursor::new("abc"));
+        run_lines(&mut simulator, &lines);
+        assert_eq!(simulator.registers[16], 3);
+        assert_eq!(simulator.read_word(BASE), u32::from_le_bytes(*b"abc\0"));
+    }
+
+    #[test]
+    fn linux_write_of_a_huge_count_is_a_short_write() {
+        let lines = [
+            "addi $a0, $zero, 1",
+            "addi $a1, $zero, 4096",
+            "addi $a2, $zero, -1",
+            "addi $v0, $zero, 4004",
+            "syscall",
+            "add $s0, $v0, $zero",
+        ];
+        let output = OutputBuffer::default();
+        let mut simulator = MipsSimulator::new();
+        simulator.abi = SyscallAbi::LinuxO32;
+        simulator.set_output(output.clone());
+        run_lines(&mut simulator, &lines);
+        assert_eq!(simulator.registers[16], 64 * 1024);
+        assert_eq!(output.0.borrow().len(), 64 * 1024);
+    }
+
+    #[test]
+    fn macros_substitute_arguments_and_rename_labels() {
+        let lines = [
+            ".macro countdown(%reg, %from)",
+            "addi %reg, $zero, %from",
+            "again: addi %reg, %reg, -1",
+            "bnez %reg, again",
+            ".end_macro",
+            "countdown($t0, 3)",
+            "countdown($t1, 2)",
+            ".macro load(%h, %l)",
+            "lui %h, %hi(%l)",
+            "addiu %h, %h, %lo(%l)",
+            ".asciiz \"%h %l\"",
+            ".end_macro",
+            "load($t2, value)",
+        ]
+        .iter()
+        .enumerate()
+        .map(|(i, text)| SourceLine {
+            text: text.to_string(),
+            line: i + 1,
+            location: format!("test.s:{}", i + 1),
+        })
+        .collect();
+        let mut simulator = MipsSimulator::new();
+        let expanded = simulator.expand_macros(lines);
+
+        let texts: Vec<&str> = expanded.iter().map(|line| line.text.as_str()).collect();
+        assert_eq!(
+            texts,
+            [
+                "addi $t0, $zero, 3",
+                "again_M1: addi $t0, $t0, -1",
+                "bnez $t0, again_M1",
+                "addi $t1, $zero, 2",
+                "again_M2: addi $t1, $t1, -1",
+                "bnez $t1, again_M2",
+                "lui $t2, %hi(value)",
+                "addiu $t2, $t2, %lo(value)",
+                ".asciiz \"%h %l\"",
+            ]
+        );
+        assert_eq!(
+            expanded[4].location,
+            "test.s:3 in macro countdown called from test.s:7"
+        );
+    }
+
+    #[test]
+    fn comments_are_ignored_outside_quotes() {
+        let source = std::env::temp_dir().join(format!("comments-{}.s", std::process::id()));
+        std::fs::write(
+            &source,
+            "# a whole-line comment\n.data\nnum1: .word 10 # ten\n\
+             text: .asciiz \"#1\" # not part of the string\n.text\n\
+             lw $t0, num1 # load\nadd $t1, $t0, $t0  # double it\n\
+             addi $t2, $zero, '#'#hash\n",
+        )
+        .unwrap();
+        let mut simulator = MipsSimulator::new();
+        simulator.load_program_from_file(&source.to_string_lossy());
+        std::fs::remove_file(&source).unwrap();
+        simulator.assemble_program();
+        simulator.run();
+        assert_eq!(simulator.registers[9], 20);
+        assert_eq!(simulator.registers[10], b'#' as i64);
+        let text = simulator.labels["text"];
+        let bytes: Vec<u8> = (0..3).map(|i| simulator.read_byte(text + i)).collect();
+        assert_eq!(bytes, b"#1\0");
+    }
+
+    #[test]
+    fn expressions_evaluate_literals_precedence_and_hi_lo() {
+        use expr::{evaluate, split_operands, strip_comment};
+        let lookup = |name: &str| (name == "table").then_some(0x1001_8000);
+        let value = |expression| evaluate(expression, &lookup);
+
+        for (expression, expected) in [
+            ("42", 42),
+            ("0x1F", 31),
+            ("0b101", 5),
+            ("017", 15),
+            ("0o17", 15),
+            ("'A'", 65),
+            ("'\\n'", 10),
+            ("-~0", 1),
+            ("1 + 2 * 3", 7),
+            ("(1 + 2) * 3", 9),
+            ("1 << 2 + 1", 8),
+            ("6 & 3 | 8", 10),
+            ("table + 4", 0x1001_8004),
+            ("%hi(table)", 0x1002),
+            ("%lo(table)", -0x8000),
+            ("%hi(0x12347FFF)", 0x1234),
+            ("(%hi(table) << 16) + %lo(table)", 0x1001_8000),
+        ] {
+            assert_eq!(value(expression), Ok(expected), "{}", expression);
+        }
+        assert!(value("missing + 1").is_err());
+        assert!(value("1 / 0").is_err());
+        assert!(value("(1 + 2").is_err());
+        assert!(value("09").is_err());
+
+        assert_eq!(split_operands("$t0, 4($sp)"), ["$t0", "4($sp)"]);
+        assert_eq!(split_operands("\"a, b\", ','"), ["\"a, b\"", "','"]);
+        assert_eq!(split_operands("1 2 3"), ["1", "2", "3"]);
+        assert_eq!(
+            strip_comment("add $t1, $t0, $t0  # double"),
+            "add $t1, $t0, $t0  "
+        );
+        assert_eq!(
+            strip_comment(".asciiz \"#\\\"#\" # x"),
+            ".asciiz \"#\\\"#\" "
+        );
+        assert_eq!(strip_comment("li $t0, '#'"), "li $t0, '#'");
+
+        let mut simulator = MipsSimulator::new();
+        simulator.labels.insert("SIZE".to_string(), 0x2000);
+        simulator.assemble_data_directive(".eqv SIZE 4 * 8", &mut vec![]);
+        simulator.assemble_data_directive(".set MASK, SIZE - 1", &mut vec![]);
+        simulator.assemble_data_directive(".set noreorder", &mut vec![]);
+        assert_eq!(simulator.try_evaluate("SIZE"), Ok(32));
+        assert_eq!(simulator.try_evaluate("MASK"), Ok(31));
+        assert!(!simulator.constants.contains_key("noreorder"));
+    }
+
+    #[test]
+    fn data_directives_align_and_bind_labels_after_padding() {
+        let source = std::env::temp_dir().join(format!("data-{}.s", std::process::id()));
+        std::fs::write(
+            &source,
+            ".data\nb: .byte 1\nh: .half 2\nw: .word 3\n.align 3\ns: .space 5\n\
+             str: .asciiz \"a\\tb\\\\\\\"\"\nlate:\n.word 9\n",
+        )
+        .unwrap();
+        let mut simulator = MipsSimulator::new();
+        simulator.load_program_from_file(&source.to_string_lossy());
+        std::fs::remove_file(&source).unwrap();
+        simulator.assemble_program();
+
+        let labels = &simulator.units[0].labels;
+        let addresses: Vec<u32> = ["b", "h", "w", "s", "str", "late"]
+            .iter()
+            .map(|label| labels[*label])
+            .collect();
+        assert_eq!(addresses, [0x1000, 0x1002, 0x1004, 0x1008, 0x100D, 0x1014]);
+        let bytes: Vec<u8> = (0x1000..0x1018)
+            .map(|address| simulator.read_byte(address))
+            .collect();
+        assert_eq!(
+            bytes,
+            [
+                1, 0, 2, 0, 3, 0, 0, 0, 0, 0, 0, 0, 0, b'a', b'\t', b'b', b'\\', b'"', 0, 0, 9, 0,
+                0, 0
+            ]
+        );
+        assert_eq!(simulator.next_free_address, 0x1018);
+
+        simulator.next_free_address = 0xFFFF_FFF0;
+        for directive in [".space 0x20", ".space 0x100000000", ".align 8"] {
+            simulator.assemble_data_directive(directive, &mut vec![]);
+            assert_eq!(simulator.next_free_address, 0xFFFF_FFF0, "{}", directive);
+        }
+        simulator.assemble_data_directive(".word 1", &mut vec![]);
+        assert_eq!(simulator.next_free_address, 0xFFFF_FFF4);
+    }
+
+    #[test]
+    fn pseudo_instruction_expansions_move_later_labels() {
+        let source = std::env::temp_dir().join(format!("pseudo-{}.s", std::process::id()));
+        std::fs::write(
+            &source,
+            ".data\nvalue: .word 7\n.text\n\
+             li $t0, 5\nli $t1, 0xFFFF\nli $t2, 0x12345678\nla $t3, value\n\
+             blt $t0, 10, less\naddi $s0, $zero, 1\n\
+             less: bge $t0, 0x10000, end\naddi $s1, $zero, 2\nend:\n",
+        )
+        .unwrap();
+        let mut simulator = MipsSimulator::new();
+        simulator.load_program_from_file(&source.to_string_lossy());
+        std::fs::remove_file(&source).unwrap();
+
+        // One instruction per line, except li 0x12345678 (2), la (2), blt with
+        // a short immediate (3) and bge with a long one (4).
+        let sizes: Vec<usize> = simulator
+            .listing
+            .iter()
+            .map(|line| line.instructions.len())
+            .filter(|&size| size > 0)
+            .collect();
+        assert_eq!(sizes, [1, 1, 2, 2, 3, 1, 4, 1]);
+        let labels = &simulator.units[0].labels;
+        assert_eq!(labels["less"], TEXT_BASE + 0x28);
+        assert_eq!(labels["end"], TEXT_BASE + 0x3C);
+
+        simulator.assemble_program();
+        simulator.run();
+        assert_eq!(simulator.registers[9], 0xFFFF);
+        assert_eq!(simulator.registers[10], 0x1234_5678);
+        assert_eq!(simulator.registers[11], DATA_BASE as i64);
+        assert_eq!(simulator.registers[16], 0);
+        assert_eq!(simulator.registers[17], 2);
+
+        assert_eq!(
+            simulator.expand_pseudo_instruction("li $t5, 0xFFFFFFFF"),
+            ["addiu $t5, $zero, -1"]
+        );
+        assert_eq!(
+            simulator.expand_pseudo_instruction("li $t5, 0x123456789"),
+            ["addiu $t5, $zero, 0"]
+        );
+        assert_eq!(
+            simulator.expand_pseudo_instruction("li $t5, -0x80000001"),
+            ["addiu $t5, $zero, 0"]
+        );
+    }
+
+    #[test]
+    fn whitespace_lists_keep_signed_elements() {
+        use expr::split_operands;
+        assert_eq!(split_operands("5 -3 7"), ["5", "-3", "7"]);
+        assert_eq!(split_operands("a + 4 +1"), ["a + 4", "+1"]);
+        assert_eq!(split_operands("5 - 3 7"), ["5 - 3", "7"]);
+        assert_eq!(split_operands("arr+ 4 x"), ["arr+ 4", "x"]);
+
+        let mut simulator = MipsSimulator::new();
+        simulator.assemble_data_directive(".word 5 -3 7", &mut vec![]);
+        let words: Vec<i32> = (0..3)
+            .map(|i| simulator.read_word(DATA_BASE + 4 * i) as i32)
+            .collect();
+        assert_eq!(words, [5, -3, 7]);
+    }
+
+    #[test]
+    fn far_labels_load_through_at_and_wide_immediates_are_rejected() {
+        let mut simulator = MipsSimulator::new();
+        let mut labels = vec![];
+        simulator.assemble_data_directive(".space 40000", &mut labels);
+        labels.push("v".to_string());
+        simulator.assemble_data_directive(".word 77", &mut labels);
+        assert_eq!(simulator.labels["v"], 0xAC40);
+
+        let expanded = simulator.expand_pseudo_instruction("lw $s1, v");
+        assert_eq!(expanded, ["lui $at, %hi(v)", "lw $s1, %lo(v)($at)"]);
+        assert_eq!(
+            simulator.expand_pseudo_instruction("lw $s1, 8($t0)").len(),
+            1
+        );
+        let lines: Vec<String> = expanded
+            .into_iter()
+            .chain(simulator.expand_pseudo_instruction("sw $s1, v + 4"))
+            .chain(["addi $s0, $zero, 0x12345".to_string()])
+            .chain(["ori $s2, $zero, 0xFFFF".to_string()])
+            .collect();
+        run_lines(&mut simulator, &lines);
+        assert_eq!(simulator.registers[17], 77);
+        assert_eq!(simulator.read_word(0xAC44), 77);
+        assert_eq!(simulator.binary_program[4] & 0xFFFF, 0);
+        assert_eq!(simulator.registers[16], 0);
+        assert_eq!(simulator.registers[18], 0xFFFF);
+    }
+
+    #[test]
+    fn separately_assembled_files_link_through_globals() {
+        let directory = std::env::temp_dir().join(format!("link-test-{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        let write = |name: &str, text: &str| {
+            let path = directory.join(name);
+            std::fs::write(&path, text).unwrap();
+            path.to_string_lossy().into_owned()
+        };
+        write("consts.s", ".eqv STEP 5\n");
+        let main = write(
+            "main.s",
+            ".include \"consts.s\"\n.globl main\n.extern add_step 4\n\
+             main: addi $a0, $zero, 1\njal add_step\nj done\n\
+             helper: addi $t9, $zero, 99\ndone: lw $s0, result\n\
+             addi $s1, $v0, 0\naddi $v0, $zero, 10\nsyscall\n\
+             .data\nresult: .word STEP\n",
+        );
+        let library = write(
+            "library.s",
+            ".globl add_step\nadd_step: addi $v0, $a0, 10\njr $ra\n\
+             helper: addi $t8, $zero, 1\n",
+        );
+        let duplicate = write("duplicate.s", ".globl main\nmain: jr $ra\n");
+
+        let mut simulator = MipsSimulator::new();
+        simulator.load_program_from_file(&main);
+        simulator.load_program_from_file(&library);
+        simulator.assemble_program();
+        simulator.run();
+        assert_eq!(simulator.registers[17], 11);
+        assert_eq!(simulator.registers[16], 5);
+        assert_eq!(simulator.registers[24], 0);
+        assert_eq!(simulator.registers[25], 0);
+
+        let mut simulator = MipsSimulator::new();
+        simulator.load_program_from_file(&main);
+        simulator.load_program_from_file(&duplicate);
+        simulator.assemble_program();
+        std::fs::remove_dir_all(&directory).unwrap();
+        assert!(simulator.binary_program.is_empty());
+    }
+
+    #[test]
+    fn elf_executables_run_and_objects_carry_relocations() {
+        let directory = std::env::temp_dir().join(format!("elf-test-{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        let source = directory.join("program.s");
+        std::fs::write(
+            &source,
+            ".data\nvalue: .word 42\n.text\n\
+             la $t0, value\nli $s2, value\nlw $s0, 0($t0)\njal function\n\
+             addi $v0, $zero, 10\nsyscall\nfunction: addi $s1, $zero, 5\njr $ra\n",
+        )
+        .unwrap();
+        let executable = directory.join("program.elf");
+        let object = directory.join("program.o");
+
+        let mut simulator = MipsSimulator::new();
+        simulator.big_endian = true;
+        simulator.load_program_from_file(&source.to_string_lossy());
+        simulator.assemble_program();
+        simulator
+            .write_elf_executable(&executable.to_string_lossy())
+            .unwrap();
+        let unit = &simulator.units[0];
+        simulator
+            .write_elf_object(unit, &object.to_string_lossy())
+            .unwrap();
+
+        let mut loaded = MipsSimulator::new();
+        loaded.load_elf(&executable.to_string_lossy());
+        assert!(loaded.big_endian);
+        loaded.run();
+        assert_eq!(loaded.registers[16], 42);
+        assert_eq!(loaded.registers[17], 5);
+        assert_eq!(loaded.registers[18], DATA_BASE as i64);
+        assert_eq!(loaded.labels["function"], TEXT_BASE + 32);
+
+        let object = std::fs::read(&object).unwrap();
+        std::fs::remove_dir_all(&directory).unwrap();
+        assert_eq!(object[16..18], [0, 1]);
+        let kinds: Vec<u32> = simulator.relocations.iter().map(|r| r.kind).collect();
+        assert_eq!(kinds, [5, 6, 5, 6, 4]);
+        assert!(object.windows(9).any(|name| name == b".rel.text"));
+    }
+
+    #[test]
+    fn elf_segments_past_the_end_of_memory_are_rejected() {
+        let path = std::env::temp_dir().join(format!("wrap-{}.elf", std::process::id()));
+        let mut simulator = MipsSimulator::new();
+        run_lines(
+            &mut simulator,
+            &["addi $t0, $zero, 1", "addi $t1, $zero, 2"],
+        );
+        simulator
+            .write_elf_executable(&path.to_string_lossy())
+            .unwrap();
+        // Move the text segment to four bytes below the top of memory.
+        let mut bytes = std::fs::read(&path).unwrap();
+        bytes[60..64].copy_from_slice(&0xFFFF_FFFCu32.to_le_bytes());
+        std::fs::write(&path, bytes).unwrap();
+
+        let mut loaded = MipsSimulator::new();
+        loaded.load_elf(&path.to_string_lossy());
+        std::fs::remove_file(&path).unwrap();
+        assert!(loaded.program.is_empty());
+    }
+
+    #[test]
+    fn objects_leave_undefined_names_to_relocations() {
+        let source = std::env::temp_dir().join(format!("extern-{}.s", std::process::id()));
+        let object = source.with_extension("o");
+        std::fs::write(
+            &source,
+            ".data\npointer: .word ext+4\n.text\njal ext\nbeq $zero, $zero, ext\n",
+        )
+        .unwrap();
+
+        let mut simulator = MipsSimulator::new();
+        simulator.objects = true;
+        simulator.load_program_from_file(&source.to_string_lossy());
+        simulator.assemble_program();
+        let unit = &simulator.units[0];
+        simulator
+            .write_elf_object(unit, &object.to_string_lossy())
+            .unwrap();
+        let bytes = std::fs::read(&object).unwrap();
+        std::fs::remove_file(&source).unwrap();
+        std::fs::remove_file(&object).unwrap();
+
+        assert_eq!(unit.undefined, BTreeSet::from(["ext".to_string()]));
+        assert_eq!(simulator.binary_program, [3 << 26, 4 << 26]);
+        let relocations: Vec<(u32, &str, u32)> = simulator
+            .relocations
+            .iter()
+            .map(|r| (r.kind, r.symbol.as_str(), r.field))
+            .collect();
+        assert_eq!(
+            relocations,
+            [(2, "ext", 4), (4, "ext", 0), (10, "ext", 0xFFFF)]
+        );
+        assert!(bytes.windows(4).any(|name| name == b"ext\0"));
+    }
+
+    #[test]
+    fn dump_formats_place_words_at_the_base_address() {
+        use export::dump_words;
+        let words = [0x3C01_1000, 0, 0, 0, 0];
+        let text =
+            |format, start| String::from_utf8(dump_words(&words, format, true, start)).unwrap();
+
+        assert_eq!(
+            dump_words(&words[..1], DumpFormat::Binary, false, 4),
+            [0, 0, 0, 0, 0x00, 0x10, 0x01, 0x3C]
+        );
+        assert_eq!(
+            text(DumpFormat::IntelHex, 0x1_FFFC),
+            ":020000040001F9\n:04FFFC003C011000B4\n:020000040002F8\n\
+             :1000000000000000000000000000000000000000F0\n:00000001FF\n"
+        );
+        assert_eq!(
+            text(DumpFormat::ReadMemH, 8),
+            "@2\n3c011000\n00000000\n00000000\n00000000\n00000000\n"
+        );
+        assert_eq!(text(DumpFormat::Logisim, 4), "v2.0 raw\n0 3c011000 4*0\n");
+        assert!(text(DumpFormat::BinaryText, 0).starts_with("00111100000000010001000000000000\n"));
+
+        let mut simulator = MipsSimulator::new();
+        simulator.assemble_data_directive(".asciiz \"fact=\"", &mut vec![]);
+        let dump =
+            |format, big_endian| simulator.dump_segment(Segment::Data, format, big_endian, None);
+        assert_eq!(dump(DumpFormat::Binary, false).unwrap(), b"fact=\0\0\0");
+        assert_eq!(
+            dump(DumpFormat::ReadMemH, false).unwrap(),
+            b"@0\n74636166\n0000003d\n"
+        );
+        assert!(dump(DumpFormat::Binary, true).is_err());
+        assert!(dump(DumpFormat::HexText, true).is_err());
+
+        let mut simulator = MipsSimulator::new();
+        simulator.big_endian = true;
+        simulator.assemble_data_directive(".asciiz \"fact=\"", &mut vec![]);
+        let dump = simulator.dump_segment(Segment::Data, DumpFormat::HexText, true, None);
+        assert_eq!(dump.unwrap(), b"66616374\n3d000000\n");
+    }
+
+    #[test]
+    fn listing_shows_code_symbols_and_references() {
+        let source = std::env::temp_dir().join(format!("listing-{}.s", std::process::id()));
+        std::fs::write(
+            &source,
+            "\n.data\ncount: .word 3\n.text\n\
+             start: lw $t0, count\nloop: addi $t0, $t0, -1\nbnez $t0, loop\n",
+        )
+        .unwrap();
+        let mut simulator = MipsSimulator::new();
+        simulator.load_program_from_file(&source.to_string_lossy());
+        simulator.assemble_program();
+        let listing = simulator.listing();
+        let name = source.to_string_lossy().into_owned();
+        std::fs::remove_file(&source).unwrap();
+
+        let lines: Vec<&str> = listing.lines().collect();
+        assert!(lines.contains(&"    2                        .data"));
+        assert!(lines.contains(&"    3  00001000  03000000  count: .word 3"));
+        assert!(lines.contains(&"    5  00400000  8c081000  start: lw $t0, count"));
+        assert!(lines.contains(&"    7  00400008  1500fffe  bnez $t0, loop"));
+        assert!(lines.contains(&format!("count  .data    00001000  local   {}", name).as_str()));
+        assert!(lines.contains(&format!("loop   defined at {0}:6; used at {0}:7", name).as_str()));
+        assert!(lines.contains(&format!("start  defined at {}:5; never used", name).as_str()));
+    }
 }
@@ -0,0 +1,180 @@
+use std::collections::HashMap;
+
+use crate::cp0::Exception;
+use crate::{DecodedInstruction, MipsSimulator};
+
+pub fn is_mips64_instruction(opcode: &str) -> bool {
+    matches!(
+        opcode,
+        "daddu"
+            | "dsubu"
+            | "daddiu"
+            | "dsll"
+            | "dsrl"
+            | "dsra"
+            | "dsll32"
+            | "dsrl32"
+            | "dsra32"
+            | "dmult"
+            | "dmultu"
+            | "ddiv"
+            | "ddivu"
+            | "ld"
+            | "sd"
+            | "lwu"
+    )
+}
+
+impl MipsSimulator {
+    // A simulator with 64-bit GPRs that also accepts the doubleword instructions.
+    pub fn new_mips64() -> Self {
+        let mut simulator = MipsSimulator::new();
+        simulator.mips64 = true;
+        simulator
+    }
+
+    pub fn assemble_mips64_instruction(&self, opcode: &str, parts: &[String]) -> u32 {
+        match opcode {
+            "daddu" | "dsubu" => {
+                let funct = if opcode == "daddu" { 45 } else { 47 };
+                let rs = self.get_register_index(&parts[2]) as u32;
+                let rt = self.get_register_index(&parts[3]) as u32;
+                let rd = self.get_register_index(&parts[1]) as u32;
+                (rs << 21) | (rt << 16) | (rd << 11) | funct
+            }
+            "dsll" | "dsrl" | "dsra" | "dsll32" | "dsrl32" | "dsra32" => {
+                let funct_map = HashMap::from([
+                    ("dsll", 56),
+                    ("dsrl", 58),
+                    ("dsra", 59),
+                    ("dsll32", 60),
+                    ("dsrl32", 62),
+                    ("dsra32", 63),
+                ]);
+                let rt = self.get_register_index(&parts[2]) as u32;
+                let rd = self.get_register_index(&parts[1]) as u32;
+                let shamt = self.unsigned_field(&parts[3], 5, "Shift amount");
+                let funct = *funct_map.get(opcode).unwrap();
+                (rt << 16) | (rd << 11) | (shamt << 6) | funct
+            }
+            "dmult" | "dmultu" | "ddiv" | "ddivu" => {
+                let funct_map =
+                    HashMap::from([("dmult", 28), ("dmultu", 29), ("ddiv", 30), ("ddivu", 31)]);
+                let rs = self.get_register_index(&parts[1]) as u32;
+                let rt = self.get_register_index(&parts[2]) as u32;
+                let funct = *funct_map.get(opcode).unwrap();
+                (rs << 21) | (rt << 16) | funct
+            }
+            "daddiu" => {
+                let rs = self.get_register_index(&parts[2]) as u32;
+                let rt = self.get_register_index(&parts[1]) as u32;
+                let immediate = self.immediate_field(&parts[3], true);
+                (25 << 26) | (rs << 21) | (rt << 16) | immediate
+            }
+            _ => {
+                let opcode_map = HashMap::from([("lwu", 39), ("ld", 55), ("sd", 63)]);
+                let opcode_num = *opcode_map.get(opcode).unwrap();
+                let rt = self.get_register_index(&parts[1]) as u32;
+                let (offset, base) = self.memory_operand(&parts[2]);
+                let rs = base.unwrap_or(0) as u32;
+                (opcode_num << 26) | (rs << 21) | (rt << 16) | offset
+            }
+        }
+    }
+
+    // Doubleword SPECIAL functions. All of them are reserved in MIPS32 mode.
+    pub fn execute_special64(&mut self, decoded: DecodedInstruction) {
+        if !self.mips64 {
+            self.raise_exception(Exception::ReservedInstruction);
+            return;
+        }
+        let rs = self.registers[decoded.rs as usize];
+        let rt = self.registers[decoded.rt as usize];
+        let rd = decoded.rd as usize;
+        let shamt = decoded.shamt as u32;
+        match decoded.funct {
+            28 => {
+                let product = rs as i128 * rt as i128;
+                self.hi = (product >> 64) as i64;
+                self.lo = product as i64;
+            }
+            29 => {
+                let product = rs as u64 as u128 * rt as u64 as u128;
+                self.hi = (product >> 64) as i64;
+                self.lo = product as i64;
+            }
+            // As with div, a zero divisor leaves HI and LO unchanged.
+            30 => {
+                if rt != 0 {
+                    self.lo = rs.wrapping_div(rt);
+                    self.hi = rs.wrapping_rem(rt);
+                }
+            }
+            31 => {
+                if let (Some(quotient), Some(remainder)) = (
+                    (rs as u64).checked_div(rt as u64),
+                    (rs as u64).checked_rem(rt as u64),
+                ) {
+                    self.lo = quotient as i64;
+                    self.hi = remainder as i64;
+                }
+            }
+            45 => self.registers[rd] = rs.wrapping_add(rt),
+            47 => self.registers[rd] = rs.wrapping_sub(rt),
+            56 => self.registers[rd] = rt << shamt,
+            58 => self.registers[rd] = ((rt as u64) >> shamt) as i64,
+            59 => self.registers[rd] = rt >> shamt,
+            60 => self.registers[rd] = rt << (shamt + 32),
+            62 => self.registers[rd] = ((rt as u64) >> (shamt + 32)) as i64,
+            63 => self.registers[rd] = rt >> (shamt + 32),
+            _ => self.raise_exception(Exception::ReservedInstruction),
+        }
+    }
+
+    pub fn execute_daddiu(&mut self, decoded: DecodedInstruction) {
+        if !self.mips64 {
+            self.raise_exception(Exception::ReservedInstruction);
+            return;
+        }
+        let rs = decoded.rs as usize;
+        let rt = decoded.rt as usize;
+        let immediate = decoded.immediate as i16 as i64;
+        self.registers[rt] = self.registers[rs].wrapping_add(immediate);
+    }
+
+    pub fn execute_lwu(&mut self, decoded: DecodedInstruction) {
+        if !self.mips64 {
+            self.raise_exception(Exception::ReservedInstruction);
+            return;
+        }
+        let rt = decoded.rt as usize;
+        let address = self.effective_address(&decoded);
+        if let Some(address) = self.check_address(address, 4, false) {
+            self.registers[rt] = self.read_word(address) as i64;
+        }
+    }
+
+    pub fn execute_ld(&mut self, decoded: DecodedInstruction) {
+        if !self.mips64 {
+            self.raise_exception(Exception::ReservedInstruction);
+            return;
+        }
+        let rt = decoded.rt as usize;
+        let address = self.effective_address(&decoded);
+        if let Some(address) = self.check_address(address, 8, false) {
+            self.registers[rt] = self.read_doubleword(address) as i64;
+        }
+    }
+
+    pub fn execute_sd(&mut self, decoded: DecodedInstruction) {
+        if !self.mips64 {
+            self.raise_exception(Exception::ReservedInstruction);
+            return;
+        }
+        let rt = decoded.rt as usize;
+        let address = self.effective_address(&decoded);
+        if let Some(address) = self.check_address(address, 8, true) {
+            self.write_doubleword(address, self.registers[rt] as u64);
+        }
+    }
+}
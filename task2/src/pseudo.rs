@@ -0,0 +1,142 @@
+use crate::expr::{referenced_symbols, split_instruction, split_memory_operand};
+use crate::MipsSimulator;
+
+const MEMORY_ACCESSES: [&str; 21] = [
+    "lb", "lh", "lwl", "lw", "lbu", "lhu", "lwr", "sb", "sh", "swl", "sw", "swr", "ll", "sc",
+    "lwc1", "swc1", "ldc1", "sdc1", "ld", "sd", "lwu",
+];
+
+fn is_register(operand: &str) -> bool {
+    operand.starts_with('$')
+}
+
+impl MipsSimulator {
+    // Expands a pseudo-instruction into the real instructions that implement
+    // it, using $at as the scratch register. Real instructions come back
+    // unchanged. The expansion is decided while loading, so that every
+    // expanded instruction has its own address before labels resolve.
+    pub fn expand_pseudo_instruction(&self, instruction: &str) -> Vec<String> {
+        let parts = split_instruction(instruction);
+        let operand = |i: usize| parts.get(i).map_or("$zero", |part| part.as_str());
+
+        match parts[0].as_str() {
+            "nop" => vec!["sll $zero, $zero, 0".to_string()],
+            "move" => vec![format!("addu {}, $zero, {}", operand(1), operand(2))],
+            "not" => vec![format!("nor {}, {}, $zero", operand(1), operand(2))],
+            "neg" => vec![format!("sub {}, $zero, {}", operand(1), operand(2))],
+            "abs" => vec![
+                format!("sra $at, {}, 31", operand(2)),
+                format!("xor {}, {}, $at", operand(1), operand(2)),
+                format!("subu {}, {}, $at", operand(1), operand(1)),
+            ],
+            "li" => self.load_immediate(operand(1), operand(2)),
+            "la" => vec![
+                format!("lui $at, %hi({})", operand(2)),
+                format!("addiu {}, $at, %lo({})", operand(1), operand(2)),
+            ],
+            "b" => vec![format!("beq $zero, $zero, {}", operand(1))],
+            "beqz" | "bnez" => {
+                let branch = if parts[0] == "beqz" { "beq" } else { "bne" };
+                vec![format!("{} {}, $zero, {}", branch, operand(1), operand(2))]
+            }
+            "blt" | "bgt" | "ble" | "bge" => {
+                // blt/bge test rs < rt; bgt/ble test rt < rs.
+                let (mut expanded, rt) = self.register_operand(operand(2));
+                let (first, second) = match parts[0].as_str() {
+                    "blt" | "bge" => (operand(1), rt.as_str()),
+                    _ => (rt.as_str(), operand(1)),
+                };
+                let branch = if matches!(parts[0].as_str(), "blt" | "bgt") {
+                    "bne"
+                } else {
+                    "beq"
+                };
+                expanded.push(format!("slt $at, {}, {}", first, second));
+                expanded.push(format!("{} $at, $zero, {}", branch, operand(3)));
+                expanded
+            }
+            "rem" => {
+                let (mut expanded, rt) = self.register_operand(operand(3));
+                expanded.push(format!("div {}, {}", operand(2), rt));
+                expanded.push(format!("mfhi {}", operand(1)));
+                expanded
+            }
+            "mul" if !is_register(operand(3)) => {
+                let mut expanded = self.load_immediate("$at", operand(3));
+                expanded.push(format!("mul {}, {}, $at", operand(1), operand(2)));
+                expanded
+            }
+            op if MEMORY_ACCESSES.contains(&op) => {
+                self.absolute_memory_access(op, operand(1), operand(2), instruction)
+            }
+            _ => vec![instruction.to_string()],
+        }
+    }
+
+    // A load or store from an absolute address such as a label goes through
+    // $at when a 16-bit offset from $zero cannot reach it, or when the address
+    // is not known yet.
+    fn absolute_memory_access(
+        &self,
+        opcode: &str,
+        register: &str,
+        address: &str,
+        instruction: &str,
+    ) -> Vec<String> {
+        let (offset, base) = split_memory_operand(address);
+        let reachable = self
+            .try_evaluate(offset)
+            .is_ok_and(|value| i16::try_from(value).is_ok());
+        if base.is_some() || offset.is_empty() || reachable {
+            return vec![instruction.to_string()];
+        }
+        vec![
+            format!("lui $at, %hi({})", offset),
+            format!("{} {}, %lo({})($at)", opcode, register, offset),
+        ]
+    }
+
+    // Pseudo-instructions that accept an immediate in place of a register
+    // load it into $at first.
+    fn register_operand(&self, operand: &str) -> (Vec<String>, String) {
+        if is_register(operand) {
+            (vec![], operand.to_string())
+        } else {
+            (self.load_immediate("$at", operand), "$at".to_string())
+        }
+    }
+
+    // li takes one instruction when the value fits a sign- or zero-extended
+    // 16-bit immediate and lui/ori through $at otherwise. A value that depends
+    // on a label, or that cannot be worked out yet, gets the long form as
+    // %hi/%lo, which an object file can relocate. A value wider than a word
+    // is reported and loads 0.
+    fn load_immediate(&self, register: &str, immediate: &str) -> Vec<String> {
+        let uses_label = referenced_symbols(immediate)
+            .iter()
+            .any(|name| self.labels.contains_key(name) && !self.constants.contains_key(name));
+        let value = self.try_evaluate(immediate).ok().filter(|_| !uses_label);
+        let too_wide =
+            |value: &i64| i32::try_from(*value).is_err() && u32::try_from(*value).is_err();
+        if let Some(value) = value.filter(too_wide) {
+            self.report_error(&format!("{} does not fit in a 32-bit register", value));
+            return vec![format!("addiu {}, $zero, 0", register)];
+        }
+        match value.map(|value| value as i32) {
+            Some(value) if i16::try_from(value).is_ok() => {
+                vec![format!("addiu {}, $zero, {}", register, value)]
+            }
+            Some(value) if u16::try_from(value).is_ok() => {
+                vec![format!("ori {}, $zero, {}", register, value)]
+            }
+            Some(value) => vec![
+                format!("lui $at, {}", (value as u32) >> 16),
+                format!("ori {}, $at, {}", register, value as u32 & 0xFFFF),
+            ],
+            None => vec![
+                format!("lui $at, %hi({})", immediate),
+                format!("addiu {}, $at, %lo({})", register, immediate),
+            ],
+        }
+    }
+}
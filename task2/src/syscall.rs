@@ -0,0 +1,124 @@
+use std::io::{BufRead, Read, Write};
+
+use crate::cp0::Exception;
+use crate::MipsSimulator;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SyscallAbi {
+    // The MARS/SPIM teaching services.
+    Mars,
+    // The Linux o32 ABI used by statically linked user-mode programs.
+    LinuxO32,
+}
+
+const PRINT_INT: i64 = 1;
+const PRINT_STRING: i64 = 4;
+const READ_INT: i64 = 5;
+const READ_STRING: i64 = 8;
+const SBRK: i64 = 9;
+const EXIT: i64 = 10;
+const PRINT_CHAR: i64 = 11;
+const READ_CHAR: i64 = 12;
+const EXIT2: i64 = 17;
+
+impl MipsSimulator {
+    pub fn set_input(&mut self, input: impl BufRead + 'static) {
+        self.input = Box::new(input);
+    }
+
+    pub fn set_output(&mut self, output: impl Write + 'static) {
+        self.output = Box::new(output);
+    }
+
+    // Services follow the MARS/SPIM table: $v0 selects the service and the
+    // arguments are in $a0/$a1. Unknown services raise a Syscall exception so
+    // that a kernel handler can still deal with them.
+    pub fn execute_syscall(&mut self) {
+        if self.abi == SyscallAbi::LinuxO32 {
+            self.execute_linux_syscall();
+            return;
+        }
+        let a0 = self.registers[4];
+        let a1 = self.registers[5];
+        match self.registers[2] {
+            PRINT_INT => self.write_output(a0.to_string().as_bytes()),
+            PRINT_STRING => {
+                let text = self.read_c_string(a0 as u32);
+                self.write_output(&text);
+            }
+            PRINT_CHAR => self.write_output(&[a0 as u8]),
+            READ_INT => {
+                let line = self.read_input_line();
+                self.registers[2] = line.trim().parse::<i32>().unwrap_or_else(|_| {
+                    self.report_syscall_error(&format!("Invalid integer input: {}", line.trim()));
+                    0
+                }) as i64;
+            }
+            READ_STRING => self.read_string(a0 as u32, a1 as i32),
+            READ_CHAR => {
+                let mut byte = [0u8; 1];
+                self.registers[2] = match self.input.read(&mut byte) {
+                    Ok(1) => byte[0] as i64,
+                    _ => -1,
+                };
+            }
+            SBRK => {
+                // The heap grows from the end of the data segment, word aligned.
+                let address = (self.next_free_address + 3) & !3;
+                self.next_free_address = address.wrapping_add(a0 as u32);
+                self.registers[2] = address as i64;
+            }
+            EXIT => self.exit_code = Some(0),
+            EXIT2 => self.exit_code = Some(a0 as i32),
+            _ => self.raise_exception(Exception::Syscall),
+        }
+    }
+
+    // Reads at most length - 1 bytes of one input line, keeping the newline if
+    // it fits, and always null-terminates the buffer as MARS does.
+    fn read_string(&mut self, address: u32, length: i32) {
+        if length < 1 {
+            return;
+        }
+        let line = self.read_input_line();
+        let count = line.len().min(length as usize - 1);
+        for (i, &byte) in line.as_bytes()[..count].iter().enumerate() {
+            self.write_byte(address.wrapping_add(i as u32), byte);
+        }
+        self.write_byte(address.wrapping_add(count as u32), 0);
+    }
+
+    fn read_input_line(&mut self) -> String {
+        let mut line = String::new();
+        self.input
+            .read_line(&mut line)
+            .expect("Could not read program input");
+        line
+    }
+
+    pub fn read_c_string(&self, address: u32) -> Vec<u8> {
+        let mut text = vec![];
+        let mut address = address;
+        loop {
+            let byte = self.read_byte(address);
+            if byte == 0 {
+                break;
+            }
+            text.push(byte);
+            address = address.wrapping_add(1);
+        }
+        text
+    }
+
+    // A service that cannot make sense of its input says so on stderr, where
+    // it does not mix with the program's own output, and names the syscall.
+    fn report_syscall_error(&self, message: &str) {
+        eprintln!("Error: {} at PC 0x{:08X}", message, self.pc);
+    }
+
+    fn write_output(&mut self, bytes: &[u8]) {
+        self.output
+            .write_all(bytes)
+            .expect("Could not write program output");
+    }
+}